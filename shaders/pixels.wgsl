@@ -5,11 +5,17 @@ struct Uniform {
     view: mat4x4f,
     image_size: vec2u,
     window_size: vec2u,
+    image_offset: u32,
 
     color: vec3f,
     scale: f32,
     progress: f32,
-    progress_angle: f32
+    progress_angle: f32,
+
+    // Used for transitions between scenes
+    opacity: f32,
+    dissolve: f32,
+    wipe: vec3f
 }
 
 @vertex
@@ -19,7 +25,12 @@ fn vert(@builtin(vertex_index) index: u32) -> VertexOutput {
 
 @fragment
 fn frag(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4(ctx.color, 1.0) * evaluate(in.uv);
+    // Only keep the side of the wipe line this scene is on. This can't discard
+    // as evaluate needs derivatives.
+    let screen = in.pos.xy / vec2f(ctx.window_size);
+    let wipe = f32(dot(screen, ctx.wipe.xy) <= ctx.wipe.z);
+
+    return vec4(ctx.color, 1.0) * evaluate(in.uv) * ctx.opacity * wipe;
 }
 
 fn evaluate(uv: vec2f) -> f32 {
@@ -29,7 +40,7 @@ fn evaluate(uv: vec2f) -> f32 {
     let dist = chebyshev_distance(pos - rounded);
 
     let progress = progress(uv);
-    let edge = dist - 0.45 * saturate(progress(uv));
+    let edge = dist - 0.45 * saturate(progress(uv)) * dissolve(vec2u(rounded));
 
     let pixel = pixel(vec2u(rounded));
     let cutoff_width = fwidth(dist);
@@ -40,7 +51,21 @@ fn evaluate(uv: vec2f) -> f32 {
 
 fn pixel(pos: vec2u) -> bool {
     let idx = pos.y * ctx.image_size.x + pos.x;
-    return (image[idx / 32] & (1u << (idx % 32))) == 0;
+    return (image[ctx.image_offset + idx / 32] & (1u << (idx % 32))) == 0;
+}
+
+// Gives each pixel a random point in the dissolve at which it starts
+// shrinking, with some overlap so they don't just pop out.
+fn dissolve(pos: vec2u) -> f32 {
+    let width = 0.25;
+    return saturate((ctx.dissolve * (1.0 + width) - hash(pos)) / width);
+}
+
+fn hash(pos: vec2u) -> f32 {
+    var x = pos.x * 1973u + pos.y * 9277u;
+    x = (x << 13u) ^ x;
+    x = x * (x * x * 15731u + 789221u) + 1376312589u;
+    return f32(x & 0x7fffffffu) / f32(0x7fffffffu);
 }
 
 fn progress(uv: vec2f) -> f32 {
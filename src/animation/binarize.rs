@@ -0,0 +1,205 @@
+use anyhow::{Result, ensure};
+use bitvec::{order::Lsb0, vec::BitVec};
+use image::RgbaImage;
+use nalgebra::Vector2;
+use serde::Deserialize;
+
+use crate::animation::Image;
+
+/// How the pixels of a scene's image are turned into the active and inactive
+/// pixels of its frames. Active pixels are set bits, which the shader leaves
+/// empty, so with the default mode black parts of an image are drawn.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Binarize {
+    pub mode: BinarizeMode,
+    /// Luminance from 0 to 1 at which pixels become active in the
+    /// `threshold` and `floyd-steinberg` modes.
+    pub threshold: f32,
+    /// Width of the Bayer matrix, one of 2, 4 or 8.
+    pub bayer_size: u32,
+    /// Palette entries that are active in `palette` mode.
+    pub indices: Vec<usize>,
+    /// Pixels less opaque than this, from 0 to 1, are always active and so
+    /// never drawn, whether or not the image is inverted.
+    pub alpha: Option<f32>,
+    pub invert: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BinarizeMode {
+    /// Pixels are active when all of their color channels are nonzero.
+    #[default]
+    Nonzero,
+    /// Pixels are active when their luminance reaches `threshold`.
+    Threshold,
+    /// Spreads the error of thresholding each pixel onto its neighbors, so
+    /// shades of grey become patterns of the matching density.
+    FloydSteinberg,
+    /// Compares pixels against a tiled Bayer matrix, for the regular
+    /// crosshatched shading of ordered dithering.
+    Bayer,
+    /// Pixels are active when their color is one of the `indices` of the
+    /// image's palette. Only PNGs and GIFs have palettes, GIFs only the
+    /// global one.
+    Palette,
+}
+
+impl Default for Binarize {
+    fn default() -> Self {
+        Self {
+            mode: BinarizeMode::default(),
+            threshold: 0.5,
+            bayer_size: 4,
+            indices: Vec::new(),
+            alpha: None,
+            invert: false,
+        }
+    }
+}
+
+impl Binarize {
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            (0.0..=1.0).contains(&self.threshold),
+            "Binarize threshold must be between 0 and 1"
+        );
+        ensure!(
+            [2, 4, 8].contains(&self.bayer_size),
+            "Bayer size must be 2, 4 or 8"
+        );
+        ensure!(
+            self.mode != BinarizeMode::Palette || !self.indices.is_empty(),
+            "Palette mode needs the `indices` of the active colors"
+        );
+
+        if let Some(alpha) = self.alpha {
+            ensure!(
+                (0.0..=1.0).contains(&alpha),
+                "Binarize alpha must be between 0 and 1"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Turns `image` into a frame, where `colors` are the palette colors at
+    /// `indices` for `palette` mode.
+    pub fn apply(&self, image: &RgbaImage, colors: &[[u8; 3]]) -> Image {
+        let (width, height) = image.dimensions();
+        let luminance = || {
+            (image.pixels())
+                .map(|x| (0.299 * x[0] as f32 + 0.587 * x[1] as f32 + 0.114 * x[2] as f32) / 255.0)
+        };
+
+        let active = match self.mode {
+            BinarizeMode::Nonzero => (image.pixels())
+                .map(|x| x[0] != 0 && x[1] != 0 && x[2] != 0)
+                .collect(),
+            BinarizeMode::Threshold => luminance().map(|x| x >= self.threshold).collect(),
+            BinarizeMode::FloydSteinberg => {
+                floyd_steinberg(luminance().collect(), width as usize, self.threshold)
+            }
+            BinarizeMode::Bayer => {
+                let (size, matrix) = (self.bayer_size, bayer(self.bayer_size));
+                (luminance().enumerate())
+                    .map(|(i, value)| {
+                        let (x, y) = (i as u32 % width % size, i as u32 / width % size);
+                        let cell = matrix[(y * size + x) as usize];
+                        value > (cell as f32 + 0.5) / (size * size) as f32
+                    })
+                    .collect()
+            }
+            BinarizeMode::Palette => (image.pixels())
+                .map(|x| colors.contains(&[x[0], x[1], x[2]]))
+                .collect(),
+        };
+
+        let mut buffer = BitVec::<u32, Lsb0>::with_capacity(active.len());
+        for (active, pixel) in active.into_iter().zip(image.pixels()) {
+            let transparent = self.alpha.is_some_and(|x| (pixel[3] as f32 / 255.0) < x);
+            buffer.push(transparent || active != self.invert);
+        }
+
+        Image {
+            data: buffer.into_vec(),
+            size: Vector2::new(width, height),
+        }
+    }
+}
+
+fn floyd_steinberg(mut luminance: Vec<f32>, width: usize, threshold: f32) -> Vec<bool> {
+    let height = luminance.len() / width.max(1);
+    let mut active = vec![false; luminance.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            active[i] = luminance[i] >= threshold;
+            let error = luminance[i] - active[i] as u32 as f32;
+
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let x = x as isize + dx;
+                if (0..width as isize).contains(&x) && y + dy < height {
+                    luminance[(y + dy) * width + x as usize] += error * weight;
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    active
+}
+
+/// Bayer matrix of `size` by `size`, built up from the 2x2 one.
+fn bayer(size: u32) -> Vec<u32> {
+    let mut matrix = vec![0];
+    let mut n = 1;
+    while n < size {
+        let mut next = vec![0; (n * n * 4) as usize];
+        for y in 0..n {
+            for x in 0..n {
+                let value = matrix[(y * n + x) as usize] * 4;
+                for (dx, dy, offset) in [(0, 0, 0), (1, 0, 2), (0, 1, 3), (1, 1, 1)] {
+                    next[((y + dy * n) * n * 2 + x + dx * n) as usize] = value + offset;
+                }
+            }
+        }
+        (matrix, n) = (next, n * 2);
+    }
+
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    /// Opaque white, opaque black, transparent white and transparent black.
+    fn image() -> RgbaImage {
+        let pixels = [
+            [255, 255, 255, 255],
+            [0, 0, 0, 255],
+            [255, 255, 255, 0],
+            [0; 4],
+        ];
+        RgbaImage::from_fn(4, 1, |x, _| Rgba(pixels[x as usize]))
+    }
+
+    #[test]
+    fn alpha_mask_is_never_drawn() {
+        let mut binarize = Binarize {
+            alpha: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(binarize.apply(&image(), &[]).data, [0b1101]);
+
+        binarize.invert = true;
+        assert_eq!(binarize.apply(&image(), &[]).data, [0b1110]);
+    }
+}
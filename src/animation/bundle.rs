@@ -0,0 +1,409 @@
+//! Container format of compiled animations. Bundles start with a fixed size
+//! header, followed by the bincode encoded [`Animation`] and an index of
+//! where each scene's frames are, then the compressed frames themselves.
+
+use std::ops::Range;
+
+use anyhow::{Context, Result, bail, ensure};
+use image::RgbImage;
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use crate::animation::{
+    Animation, Image, Playback, RuntimeConfig, SceneData,
+    colormap::Colormap,
+    config::PropertyKeyframe,
+    ease::Ease,
+    frames::{FrameIndex, Frames, Source},
+    playlist::Playlist,
+    properties::{OptionalProperties, Properties},
+    timeline::{Curve, PropertiesTimeline},
+};
+
+const MAGIC: [u8; 8] = *b"MACANIM\0";
+/// Written by [`encode`], bump whenever the encoding of [`Animation`] changes
+/// and add a migration to [`decode_payload`].
+pub const VERSION: u16 = 1;
+/// Feature flags this build understands, bundles using any others are
+/// rejected.
+pub const SUPPORTED_FLAGS: u16 = 0;
+
+pub const HEADER_SIZE: usize = 20;
+
+/// Version 0 bundles always cycled through the colormap in a minute.
+const V0_COLORMAP_DURATION: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub version: u16,
+    pub flags: u16,
+    /// Length of the payload following the header, not including the frames
+    /// after it.
+    pub length: u32,
+    /// CRC32 of the payload.
+    pub checksum: u32,
+}
+
+impl Header {
+    fn new(flags: u16, payload: &[u8]) -> Self {
+        Self {
+            version: VERSION,
+            flags,
+            length: payload.len() as u32,
+            checksum: crc32fast::hash(payload),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut out = [0; HEADER_SIZE];
+        out[0..8].copy_from_slice(&MAGIC);
+        out[8..10].copy_from_slice(&self.version.to_le_bytes());
+        out[10..12].copy_from_slice(&self.flags.to_le_bytes());
+        out[12..16].copy_from_slice(&self.length.to_le_bytes());
+        out[16..20].copy_from_slice(&self.checksum.to_le_bytes());
+        out
+    }
+
+    /// Parses the header at the start of `data`, `None` if it doesn't start
+    /// with the magic number.
+    pub fn parse(data: &[u8]) -> Result<Option<Self>> {
+        if !data.starts_with(&MAGIC) {
+            return Ok(None);
+        }
+
+        ensure!(
+            data.len() >= HEADER_SIZE,
+            "Bundle is truncated, the header needs {HEADER_SIZE} bytes but there are only {}",
+            data.len()
+        );
+
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        Ok(Some(Self {
+            version: u16_at(8),
+            flags: u16_at(10),
+            length: u32_at(12),
+            checksum: u32_at(16),
+        }))
+    }
+}
+
+pub fn encode(animation: &Animation) -> Result<Vec<u8>> {
+    let mut frames = Vec::new();
+    let index = (animation.scenes.iter())
+        .map(|scene| {
+            let data = scene.frames.to_compressed();
+            let index = FrameIndex {
+                len: scene.frames.len() as u32,
+                byte_size: scene.frames.byte_size(),
+                offset: frames.len() as u64,
+                length: data.len() as u64,
+                checksum: crc32fast::hash(&data),
+            };
+            frames.extend_from_slice(&data);
+            index
+        })
+        .collect::<Vec<_>>();
+
+    let payload = bincode::serde::encode_to_vec((animation, index), bincode::config::standard())?;
+    let header = Header::new(0, &payload);
+
+    let mut out = Vec::with_capacity(HEADER_SIZE + payload.len() + frames.len());
+    out.extend_from_slice(&header.to_bytes());
+    out.extend_from_slice(&payload);
+    out.extend_from_slice(&frames);
+    Ok(out)
+}
+
+/// Decodes a bundle, leaving the frames of each scene in `source` until they
+/// are needed.
+pub fn decode(source: Source) -> Result<Animation> {
+    let data = (*source).as_ref();
+
+    // Bundles from before the header was added are just the payload
+    let Some(header) = Header::parse(data)? else {
+        return decode_payload(0, &source, 0..data.len()).context(
+            "Not an animation bundle, or one from an older version that can't be read anymore",
+        );
+    };
+
+    ensure!(
+        header.version <= VERSION,
+        "Bundle is version {}, but only up to version {VERSION} is supported. \
+         Rebuild it or update the screensaver",
+        header.version
+    );
+
+    let unsupported = header.flags & !SUPPORTED_FLAGS;
+    ensure!(
+        unsupported == 0,
+        "Bundle uses unsupported features (flags {unsupported:#06x})"
+    );
+
+    let payload = HEADER_SIZE..HEADER_SIZE + header.length as usize;
+    ensure!(
+        data.len() >= payload.end,
+        "Bundle is truncated, expected {} bytes of data but found {}",
+        header.length,
+        data.len() - HEADER_SIZE
+    );
+
+    ensure!(
+        crc32fast::hash(&data[payload.clone()]) == header.checksum,
+        "Bundle is corrupted, its checksum doesn't match"
+    );
+
+    decode_payload(header.version, &source, payload)
+        .with_context(|| format!("Failed to decode version {} bundle", header.version))
+}
+
+/// Decodes the payload in `range` of a bundle written by `version`, where
+/// version 0 is the headerless format from before versioning.
+fn decode_payload(version: u16, source: &Source, range: Range<usize>) -> Result<Animation> {
+    let payload = &(**source).as_ref()[range.clone()];
+    match version {
+        0 => decode_bincode::<V0Animation>(payload)?.migrate(),
+        1 => {
+            let (mut animation, index) = decode_bincode::<(Animation, Vec<FrameIndex>)>(payload)?;
+            ensure!(
+                index.len() == animation.scenes.len(),
+                "Bundle has {} scenes, but frames for {}",
+                animation.scenes.len(),
+                index.len()
+            );
+
+            // Frames are stored after the payload
+            let len = (**source).as_ref().len();
+            for (scene, index) in animation.scenes.iter_mut().zip(index) {
+                let start = range.end + index.offset as usize;
+                let frames = start..start + index.length as usize;
+                ensure!(frames.end <= len, "Bundle is truncated, frames are missing");
+
+                scene.frames = Frames::compressed(
+                    source.clone(),
+                    frames,
+                    index.len as usize,
+                    index.byte_size,
+                    index.checksum,
+                );
+            }
+
+            Ok(animation)
+        }
+        _ => bail!("Unknown bundle version {version}"),
+    }
+}
+
+fn decode_bincode<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> Result<T> {
+    let (out, read) = bincode::serde::decode_from_slice(payload, bincode::config::standard())?;
+    ensure!(read == payload.len(), "Unexpected data after the animation");
+    Ok(out)
+}
+
+/// Layout of the headerless bundles written before the header was added,
+/// back when scenes had no weights, transitions or eases.
+#[derive(Deserialize)]
+struct V0Animation {
+    colormap: V0Image,
+    scenes: Vec<V0SceneData>,
+    defaults: Properties,
+}
+
+#[derive(Deserialize)]
+struct V0Image {
+    width: u32,
+    data: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct V0SceneData {
+    frames: Vec<Image>,
+    duration: f32,
+    properties: OptionalProperties,
+    timeline: V0Timeline,
+}
+
+#[derive(Deserialize)]
+struct V0Timeline {
+    camera_pos: Vec<V0Keyframe<Vector3<f32>>>,
+    camera_dir: Vec<V0Keyframe<Vector3<f32>>>,
+    scale: Vec<V0Keyframe<f32>>,
+    frame: Vec<V0Keyframe<usize>>,
+    progress: Vec<V0Keyframe<f32>>,
+    progress_angle: Vec<V0Keyframe<f32>>,
+}
+
+#[derive(Deserialize)]
+struct V0Keyframe<T> {
+    t: f32,
+    value: T,
+}
+
+impl V0Animation {
+    fn migrate(self) -> Result<Animation> {
+        let V0Image { width, data } = self.colormap;
+        ensure!(width > 0, "Colormap is empty");
+        let height = data.len() as u32 / width / 3;
+        let colormap = RgbImage::from_raw(width, height, data).context("Colormap is truncated")?;
+
+        let scenes = self.scenes.into_iter().map(|scene| SceneData {
+            frames: Frames::new(scene.frames),
+            duration: scene.duration,
+            weight: 1.0,
+            transition: None,
+            properties: scene.properties,
+            timeline: PropertiesTimeline::new(&scene.timeline.keyframes(), Curve::Linear),
+            frame_timing: None,
+        });
+
+        Ok(Animation {
+            colormap: Colormap::new(colormap.into(), V0_COLORMAP_DURATION, None),
+            scenes: scenes.collect(),
+            defaults: self.defaults,
+            playlist: Playlist::default(),
+
+            playback: Playback::default(),
+            keyframe: 0,
+            runtime: RuntimeConfig::default(),
+        })
+    }
+}
+
+impl V0Timeline {
+    /// Splits the timeline back into keyframes setting one property each.
+    fn keyframes(self) -> Vec<PropertyKeyframe> {
+        fn convert<T>(
+            keyframes: Vec<V0Keyframe<T>>,
+            set: impl Fn(T) -> OptionalProperties,
+        ) -> impl Iterator<Item = PropertyKeyframe> {
+            keyframes.into_iter().map(move |x| PropertyKeyframe {
+                t: x.t,
+                ease: Ease::Linear,
+                properties: set(x.value),
+            })
+        }
+
+        let none = OptionalProperties::default;
+        (convert(self.camera_pos, |x| OptionalProperties {
+            camera_pos: Some(x),
+            ..none()
+        }))
+        .chain(convert(self.camera_dir, |x| OptionalProperties {
+            camera_dir: Some(x),
+            ..none()
+        }))
+        .chain(convert(self.scale, |x| OptionalProperties {
+            scale: Some(x),
+            ..none()
+        }))
+        .chain(convert(self.frame, |x| OptionalProperties {
+            frame: Some(x),
+            ..none()
+        }))
+        .chain(convert(self.progress, |x| OptionalProperties {
+            progress: Some(x),
+            ..none()
+        }))
+        .chain(convert(self.progress_angle, |x| OptionalProperties {
+            progress_angle: Some(x),
+            ..none()
+        }))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+
+    use super::*;
+
+    /// Exported by the code from before bundles had a header, from a config
+    /// with a two frame 5x6 strip and a 3x4 colormap.
+    const V0: &[u8] = include_bytes!("../../tests/fixtures/v0.bin");
+
+    #[test]
+    fn decodes_v0() {
+        let animation = Animation::load(V0).unwrap();
+        assert_eq!(animation.colormap.height(), 4);
+        assert_eq!(animation.colormap.duration, V0_COLORMAP_DURATION);
+        assert_eq!(animation.defaults.scale, 4.0);
+        assert!(matches!(animation.playlist, Playlist::Sequential));
+
+        let scene = &animation.scenes[0];
+        assert_eq!((scene.duration, scene.weight), (4.0, 1.0));
+        assert_eq!(scene.properties.scale, Some(2.5));
+
+        let frames = scene.frames.load().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].size, Vector2::new(5, 3));
+        // Black where (x + y) % 3 == 0, white elsewhere
+        assert_eq!(frames[0].data[0] & 0b111, 0b110);
+
+        let properties = scene.timeline.get(0.5);
+        assert_eq!(properties.camera_pos, Some(Vector3::new(1.0, 2.0, -1.3)));
+        assert_eq!(properties.progress, Some(0.5));
+        assert_eq!(properties.frame, Some(0));
+        assert_eq!(scene.timeline.get(3.0).frame, Some(1));
+
+        assert_eq!(animation.scenes[1].frames.len(), 1);
+    }
+
+    /// The error loading `data` fails with, including its context.
+    fn error(data: &[u8]) -> String {
+        match Animation::load(data) {
+            Ok(_) => panic!("Loaded an invalid bundle"),
+            Err(err) => format!("{err:#}"),
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_data() {
+        let err = error(b"GIF89a\x01\x00\x01\x00");
+        assert!(err.contains("Not an animation bundle"));
+    }
+
+    #[test]
+    fn rejects_truncated_bundles() {
+        let bundle = Animation::load(V0).unwrap().encode().unwrap();
+        assert!(Header::parse(&bundle[..HEADER_SIZE - 1]).is_err());
+
+        let err = error(&bundle[..HEADER_SIZE + 4]);
+        assert!(err.contains("truncated"));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut bundle = Animation::load(V0).unwrap().encode().unwrap();
+        let header = Header::parse(&bundle).unwrap().unwrap();
+        assert_eq!((header.version, header.flags), (VERSION, 0));
+
+        bundle[8..10].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = error(&bundle);
+        assert!(err.contains("only up to version"));
+
+        bundle[8..10].copy_from_slice(&VERSION.to_le_bytes());
+        bundle[10..12].copy_from_slice(&0x8000u16.to_le_bytes());
+        let err = error(&bundle);
+        assert!(err.contains("unsupported features"));
+    }
+
+    #[test]
+    fn rejects_corrupted_payloads() {
+        let mut bundle = Animation::load(V0).unwrap().encode().unwrap();
+        bundle[HEADER_SIZE + 2] ^= 0xff;
+        let err = error(&bundle);
+        assert!(err.contains("checksum"));
+    }
+
+    #[test]
+    fn round_trips_v0() {
+        let animation = Animation::load(V0).unwrap();
+        let decoded = Animation::load(&animation.encode().unwrap()).unwrap();
+        assert_eq!(decoded.scenes(), 2);
+        assert_eq!(
+            decoded.scenes[0].frames.load().unwrap()[1].data,
+            animation.scenes[0].frames.load().unwrap()[1].data
+        );
+    }
+}
@@ -1,6 +1,5 @@
 use image::{DynamicImage, EncodableLayout, Rgb, RgbImage};
 use nalgebra::Vector3;
-use rand::Rng;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Serialize, Deserialize)]
@@ -10,8 +9,11 @@ pub struct Colormap {
         deserialize_with = "deserialize_image"
     )]
     inner: RgbImage,
-    #[serde(skip, default = "random_offset")]
-    offset: f32,
+    /// Seconds it takes to cycle through every row of the colormap.
+    pub duration: f32,
+    /// Fixed starting point in the cycle (0-1), otherwise each
+    /// [`Playback`](super::Playback) picks a random offset.
+    pub phase: Option<f32>,
 }
 
 const COLUMN_BACKGROUND_TOP: u32 = 0;
@@ -19,13 +21,24 @@ const COLUMN_BACGKROUND_BOTTOM: u32 = 1;
 const COLUMN_FOREGROUND: u32 = 2;
 
 impl Colormap {
-    pub fn new(image: DynamicImage) -> Self {
+    pub fn new(image: DynamicImage, duration: f32, phase: Option<f32>) -> Self {
         Self {
             inner: image.into_rgb8(),
-            offset: random_offset(),
+            duration,
+            phase,
         }
     }
 
+    /// Converts a time in seconds into a position in the colormap cycle,
+    /// starting from `offset` unless the colormap has a fixed phase.
+    pub fn cycle(&self, time: f32, offset: f32) -> f32 {
+        (time / self.duration + self.phase.unwrap_or(offset)).rem_euclid(1.0)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.inner.height()
+    }
+
     pub fn get_background_top(&self, t: f32) -> Vector3<f32> {
         self.get_color(COLUMN_BACKGROUND_TOP, t)
     }
@@ -40,7 +53,7 @@ impl Colormap {
 
     fn get_color(&self, x: u32, t: f32) -> Vector3<f32> {
         let height = self.inner.height() as f32;
-        let px = height * ((t + self.offset) % 1.0);
+        let px = (height * t) % height;
 
         let low = self.inner.get_pixel(x, px.floor() as u32);
         let high = self.inner.get_pixel(x, (px.ceil() % height) as u32);
@@ -73,7 +86,3 @@ fn deserialize_image<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RgbIm
     let image = RgbImage::from_raw(raw.width, height, raw.data).unwrap();
     Ok(image)
 }
-
-fn random_offset() -> f32 {
-    rand::rng().random()
-}
@@ -2,7 +2,14 @@ use std::path::PathBuf;
 
 use serde::Deserialize;
 
-use crate::animation::properties::{OptionalProperties, Properties};
+use crate::animation::{
+    binarize::Binarize,
+    ease::Ease,
+    playlist::Playlist,
+    properties::{OptionalProperties, Properties},
+    timeline::Curve,
+    transition::Transition,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct AnimationConfig {
@@ -14,21 +21,40 @@ pub struct AnimationConfig {
 pub struct BackgroundConfig {
     pub colormap: PathBuf,
     pub duration: f32,
+    pub phase: Option<f32>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ScenesConfig {
     #[serde(flatten)]
     pub properties: Properties,
+    #[serde(default)]
+    pub playlist: Playlist,
     pub scene: Vec<SceneConfig>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct SceneConfig {
+    /// A still image with the frames stacked vertically, an animated GIF or
+    /// APNG, a directory of numbered PNGs or a pattern like `walk-*.png`.
     pub image: PathBuf,
+    /// Number of frames in a still image.
     #[serde(default = "default_frames")]
     pub frames: u32,
+    /// Plays the frames at a fixed rate, instead of the delays stored in
+    /// animated images.
+    pub fps: Option<f32>,
+    /// How the image's pixels become active or inactive.
+    #[serde(default)]
+    pub binarize: Binarize,
     pub duration: f32,
+    /// Relative chance of being picked by a weighted playlist.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    #[serde(default)]
+    pub curve: Curve,
+    /// How this scene replaces the one before it.
+    pub transition: Option<Transition>,
 
     #[serde(flatten)]
     pub properties: OptionalProperties,
@@ -38,6 +64,8 @@ pub struct SceneConfig {
 #[derive(Deserialize, Debug)]
 pub struct PropertyKeyframe {
     pub t: f32,
+    #[serde(default)]
+    pub ease: Ease,
     #[serde(flatten)]
     pub properties: OptionalProperties,
 }
@@ -45,3 +73,7 @@ pub struct PropertyKeyframe {
 fn default_frames() -> u32 {
     1
 }
+
+fn default_weight() -> f32 {
+    1.0
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+/// Timing curve applied to the interpolation between a keyframe and the one
+/// after it.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ease {
+    #[default]
+    Linear,
+    /// Holds the value until the next keyframe.
+    Step,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Smoothstep,
+    /// Same as the CSS `cubic-bezier(x1, y1, x2, y2)` timing function.
+    CubicBezier([f32; 4]),
+}
+
+impl Ease {
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Ease::Linear => t,
+            Ease::Step => (t >= 1.0) as u32 as f32,
+            Ease::EaseIn => cubic_bezier([0.42, 0.0, 1.0, 1.0], t),
+            Ease::EaseOut => cubic_bezier([0.0, 0.0, 0.58, 1.0], t),
+            Ease::EaseInOut => cubic_bezier([0.42, 0.0, 0.58, 1.0], t),
+            Ease::Smoothstep => t * t * (3.0 - 2.0 * t),
+            Ease::CubicBezier(points) => cubic_bezier(*points, t),
+        }
+    }
+}
+
+/// Evaluates a bezier curve from (0, 0) to (1, 1) with the given control
+/// points, by solving for the curve parameter that lands on `x`.
+fn cubic_bezier([x1, y1, x2, y2]: [f32; 4], x: f32) -> f32 {
+    let bezier = |a: f32, b: f32, t: f32| {
+        let u = 1.0 - t;
+        3.0 * u * u * t * a + 3.0 * u * t * t * b + t * t * t
+    };
+    let derivative = |a: f32, b: f32, t: f32| {
+        let u = 1.0 - t;
+        3.0 * u * u * a + 6.0 * u * t * (b - a) + 3.0 * t * t * (1.0 - b)
+    };
+
+    let x = x.clamp(0.0, 1.0);
+    let mut t = x;
+    for _ in 0..8 {
+        let error = bezier(x1, x2, t) - x;
+        let slope = derivative(x1, x2, t);
+        if error.abs() < 1e-6 || slope.abs() < 1e-6 {
+            break;
+        }
+
+        t = (t - error / slope).clamp(0.0, 1.0);
+    }
+
+    // Newton's method can stall on steep curves, so finish off with bisection
+    if (bezier(x1, x2, t) - x).abs() > 1e-4 {
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..32 {
+            t = (low + high) / 2.0;
+            if bezier(x1, x2, t) < x {
+                low = t;
+            } else {
+                high = t;
+            }
+        }
+    }
+
+    bezier(y1, y2, t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cubic_bezier_hits_endpoints() {
+        let curves = [
+            [0.25, 0.1, 0.25, 1.0],
+            [0.0, 1.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            [0.5, -0.5, 0.5, 1.5],
+        ];
+
+        for points in curves {
+            let ease = Ease::CubicBezier(points);
+            assert!(ease.apply(0.0).abs() < 1e-4, "{points:?}");
+            assert!((ease.apply(1.0) - 1.0).abs() < 1e-4, "{points:?}");
+            assert!(ease.apply(-1.0).abs() < 1e-4, "{points:?}");
+            assert!((ease.apply(2.0) - 1.0).abs() < 1e-4, "{points:?}");
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    io::{Read, Write},
+    ops::Range,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail, ensure};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use nalgebra::Vector2;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::Image;
+
+/// Bytes of a bundle, either read into memory or memory mapped.
+pub type Source = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// Frames of a scene. Frames from bundles stay compressed until they are
+/// first drawn and can be evicted again once unused, so large bundles don't
+/// have to fit into memory at once.
+#[derive(Clone)]
+pub struct Frames(Arc<Inner>);
+
+/// Where the compressed frames of a scene are in the data section of a
+/// bundle. Each frame is XORed with the previous one if they are the same
+/// size, so the mostly unchanged frames of animated scenes become runs of
+/// zeros that deflate well.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct FrameIndex {
+    pub len: u32,
+    pub byte_size: u64,
+    pub offset: u64,
+    pub length: u64,
+    /// CRC32 of the compressed frames.
+    pub checksum: u32,
+}
+
+struct Inner {
+    len: usize,
+    byte_size: u64,
+    /// Compressed frames, `None` for frames that were never compressed and
+    /// stay in memory.
+    source: Option<Compressed>,
+    state: Mutex<State>,
+}
+
+struct Compressed {
+    source: Source,
+    range: Range<usize>,
+    checksum: u32,
+}
+
+struct State {
+    images: Option<Arc<[Image]>>,
+    last_used: Instant,
+    /// Set while a prefetch is decompressing the frames.
+    loading: bool,
+}
+
+impl Frames {
+    pub fn new(images: Vec<Image>) -> Self {
+        Self(Arc::new(Inner {
+            len: images.len(),
+            byte_size: images.iter().map(|x| x.byte_size()).sum(),
+            source: None,
+            state: Mutex::new(State::new(Some(images.into()))),
+        }))
+    }
+
+    /// Frames compressed into `range` of `source`, verified against
+    /// `checksum` when loaded.
+    pub fn compressed(
+        source: Source,
+        range: Range<usize>,
+        len: usize,
+        byte_size: u64,
+        checksum: u32,
+    ) -> Self {
+        Self(Arc::new(Inner {
+            len,
+            byte_size,
+            source: Some(Compressed {
+                source,
+                range,
+                checksum,
+            }),
+            state: Mutex::new(State::new(None)),
+        }))
+    }
+
+    /// Number of frames, without decompressing them.
+    pub fn len(&self) -> usize {
+        self.0.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.len == 0
+    }
+
+    /// Size in bytes of the bitmaps of every frame, without decompressing
+    /// them.
+    pub fn byte_size(&self) -> u64 {
+        self.0.byte_size
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.0.state.lock().images.is_some()
+    }
+
+    /// Decompresses the frames if they aren't loaded already. Checking every
+    /// scene up front would read the whole bundle, so corrupted scenes only
+    /// fail here.
+    pub fn load(&self) -> Result<Arc<[Image]>> {
+        {
+            let mut state = self.0.state.lock();
+            state.last_used = Instant::now();
+            if let Some(images) = &state.images {
+                return Ok(images.clone());
+            }
+        }
+
+        // Decompressed without holding the lock, so the render thread isn't
+        // blocked by a prefetch of another scene's frames
+        let source = self.0.source.as_ref().unwrap();
+        let images = Arc::<[Image]>::from(source.decompress(self.0.len)?);
+        Ok(self.0.state.lock().images.get_or_insert(images).clone())
+    }
+
+    /// Like [`Frames::load`], but falls back to blank frames so a corrupted
+    /// scene doesn't take down the screensaver. The blank frames are kept
+    /// until evicted, so the error is only printed once each time the scene
+    /// plays.
+    pub fn load_or_blank(&self) -> Arc<[Image]> {
+        self.load().unwrap_or_else(|err| {
+            eprintln!("Failed to load scene: {err:#}");
+            let blank = (0..self.0.len.max(1))
+                .map(|_| Image::blank())
+                .collect::<Arc<[_]>>();
+            self.0.state.lock().images = Some(blank.clone());
+            blank
+        })
+    }
+
+    /// Starts decompressing the frames on another thread, so they are ready
+    /// by the time they are drawn.
+    pub fn prefetch(&self) {
+        // Called every frame, so try again next frame rather than waiting
+        let Some(mut state) = self.0.state.try_lock() else {
+            return;
+        };
+
+        if self.0.source.is_none() || state.images.is_some() || state.loading {
+            return;
+        }
+
+        state.loading = true;
+        let frames = self.clone();
+        thread::spawn(move || {
+            // Errors are reported once the scene is drawn
+            let _ = frames.load();
+            frames.0.state.lock().loading = false;
+        });
+    }
+
+    /// Drops decompressed frames that haven't been used for `unused`, frames
+    /// that can't be decompressed again are kept.
+    pub fn evict(&self, unused: Duration) {
+        // Skip frames being prefetched rather than waiting for them
+        let Some(mut state) = self.0.state.try_lock() else {
+            return;
+        };
+
+        if self.0.source.is_some() && !state.loading && state.last_used.elapsed() > unused {
+            state.images = None;
+        }
+    }
+
+    /// The frames in the compressed form stored in bundles.
+    pub fn to_compressed(&self) -> Vec<u8> {
+        match &self.0.source {
+            Some(source) => source.bytes().to_vec(),
+            None => compress(self.0.state.lock().images.as_ref().unwrap()),
+        }
+    }
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl From<Vec<Image>> for Frames {
+    fn from(images: Vec<Image>) -> Self {
+        Self::new(images)
+    }
+}
+
+impl Compressed {
+    fn bytes(&self) -> &[u8] {
+        &(*self.source).as_ref()[self.range.clone()]
+    }
+
+    fn decompress(&self, len: usize) -> Result<Vec<Image>> {
+        let data = self.bytes();
+        ensure!(
+            crc32fast::hash(data) == self.checksum,
+            "Scene is corrupted, its checksum doesn't match"
+        );
+
+        decompress(data, len)
+    }
+}
+
+impl State {
+    fn new(images: Option<Arc<[Image]>>) -> Self {
+        Self {
+            images,
+            last_used: Instant::now(),
+            loading: false,
+        }
+    }
+}
+
+fn compress(images: &[Image]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    let mut previous = None::<&Image>;
+    for image in images {
+        let mut bytes = Vec::with_capacity(8 + image.byte_size() as usize);
+        bytes.extend_from_slice(&image.size.x.to_le_bytes());
+        bytes.extend_from_slice(&image.size.y.to_le_bytes());
+
+        for (i, &word) in image.data.iter().enumerate() {
+            let word = match previous {
+                Some(previous) if previous.size == image.size => word ^ previous.data[i],
+                _ => word,
+            };
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+
+        encoder.write_all(&bytes).unwrap();
+        previous = Some(image);
+    }
+
+    encoder.finish().unwrap()
+}
+
+fn decompress(data: &[u8], len: usize) -> Result<Vec<Image>> {
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(data).read_to_end(&mut bytes)?;
+
+    let mut words = bytes
+        .chunks_exact(4)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()));
+    let mut images = Vec::<Image>::with_capacity(len);
+    for _ in 0..len {
+        let (Some(x), Some(y)) = (words.next(), words.next()) else {
+            bail!("Frame data is truncated");
+        };
+        let size = Vector2::new(x, y);
+        let count = (Image::byte_size_for(size) / 4) as usize;
+
+        let mut data = words.by_ref().take(count).collect::<Vec<_>>();
+        ensure!(data.len() == count, "Frame data is truncated");
+
+        if let Some(previous) = images.last().filter(|x| x.size == size) {
+            for (word, previous) in data.iter_mut().zip(&previous.data) {
+                *word ^= previous;
+            }
+        }
+
+        images.push(Image { data, size });
+    }
+
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, seed: u32) -> Image {
+        let size = Vector2::new(width, height);
+        let words = Image::byte_size_for(size) / 4;
+        Image {
+            data: (0..words as u32).map(|x| x.wrapping_mul(seed)).collect(),
+            size,
+        }
+    }
+
+    #[test]
+    fn round_trips_frames_of_different_sizes() {
+        let images = vec![
+            image(40, 3, 0x9e37_79b9),
+            image(40, 3, 0x85eb_ca6b),
+            image(7, 5, 0xc2b2_ae35),
+            image(7, 5, 0x27d4_eb2f),
+            image(40, 3, 0x1656_67b1),
+        ];
+
+        let decompressed = decompress(&compress(&images), images.len()).unwrap();
+        assert_eq!(decompressed.len(), images.len());
+        for (a, b) in decompressed.iter().zip(&images) {
+            assert_eq!((a.size, &a.data), (b.size, &b.data));
+        }
+
+        // More frames than were compressed
+        assert!(decompress(&compress(&images), images.len() + 1).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupted_frames() {
+        let data = compress(&[image(8, 8, 3)]);
+        let checksum = crc32fast::hash(&data);
+        let len = data.len();
+
+        let frames = Frames::compressed(Arc::new(data.clone()), 0..len, 1, 8, checksum);
+        assert_eq!(frames.load().unwrap()[0].data, image(8, 8, 3).data);
+
+        let mut corrupted = data;
+        corrupted[len / 2] ^= 0xff;
+        let frames = Frames::compressed(Arc::new(corrupted), 0..len, 1, 8, checksum);
+        assert!(frames.load().is_err());
+        assert_eq!(frames.load_or_blank()[0].data, Image::blank().data);
+    }
+}
@@ -0,0 +1,243 @@
+//! Turns the images referenced by scene configs into frames. A scene's image
+//! is either a still image holding its frames stacked vertically, an animated
+//! GIF or APNG, a directory of numbered PNGs or a pattern like
+//! `frames/walk-*.png` matching them.
+
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use image::{
+    AnimationDecoder, DynamicImage, Frame, GenericImageView, ImageFormat, ImageReader,
+    codecs::{gif::GifDecoder, png::PngDecoder},
+};
+
+use crate::animation::{
+    Image,
+    binarize::{Binarize, BinarizeMode},
+    config::SceneConfig,
+};
+
+/// Delays at or below this many seconds are treated as 0.1s, like browsers
+/// do, since many GIFs leave the delay unset.
+const MIN_DELAY: f32 = 0.01;
+const DEFAULT_DELAY: f32 = 0.1;
+
+pub struct Imported {
+    pub frames: Vec<Image>,
+    /// How long each frame is shown for, if the source has any timing.
+    pub delays: Option<Vec<f32>>,
+}
+
+pub fn load(dir: &Path, scene: &SceneConfig) -> Result<Imported> {
+    let path = dir.join(&scene.image);
+    let is_pattern = (path.file_name()).is_some_and(|x| x.to_string_lossy().contains('*'));
+
+    let load = || {
+        scene.binarize.validate()?;
+        match path.is_dir() || is_pattern {
+            true => sequence(&path, &scene.binarize),
+            false => file(&path, scene.frames, &scene.binarize),
+        }
+    };
+    let mut imported =
+        load().with_context(|| format!("Failed to load {}", scene.image.display()))?;
+
+    if let Some(fps) = scene.fps {
+        ensure!(
+            fps > 0.0,
+            "The fps of {} must be positive",
+            scene.image.display()
+        );
+        imported.delays = Some(vec![1.0 / fps; imported.frames.len()]);
+    }
+
+    Ok(imported)
+}
+
+fn file(path: &Path, frames: u32, binarize: &Binarize) -> Result<Imported> {
+    let colors = palette_colors(path, binarize)?;
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let animated = match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+            Some(decoder.into_frames().collect_frames()?)
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(BufReader::new(File::open(path)?))?;
+            match decoder.is_apng()? {
+                true => Some(decoder.apng()?.into_frames().collect_frames()?),
+                false => None,
+            }
+        }
+        _ => None,
+    };
+
+    // Single frame GIFs and PNGs can still be strips
+    if let Some(animated) = animated.filter(|x| x.len() > 1) {
+        ensure!(
+            frames == 1,
+            "`frames` only applies to still images, animated ones bring their own frames"
+        );
+        return Ok(from_animated(animated, binarize, &colors));
+    }
+
+    strip(&reader.decode()?, frames, binarize, &colors)
+}
+
+fn strip(
+    image: &DynamicImage,
+    frames: u32,
+    binarize: &Binarize,
+    colors: &[[u8; 3]],
+) -> Result<Imported> {
+    ensure!(frames > 0, "Scenes need at least one frame");
+    let height = image.height() / frames;
+    let frames = (0..frames)
+        .map(|frame| {
+            let frame = image.view(0, height * frame, image.width(), height);
+            binarize.apply(&frame.to_image(), colors)
+        })
+        .collect();
+
+    Ok(Imported {
+        frames,
+        delays: None,
+    })
+}
+
+fn from_animated(frames: Vec<Frame>, binarize: &Binarize, colors: &[[u8; 3]]) -> Imported {
+    let delays = (frames.iter())
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay = numer as f32 / denom as f32 / 1000.0;
+            if delay <= MIN_DELAY {
+                DEFAULT_DELAY
+            } else {
+                delay
+            }
+        })
+        .collect();
+
+    Imported {
+        frames: (frames.iter())
+            .map(|x| binarize.apply(x.buffer(), colors))
+            .collect(),
+        delays: Some(delays),
+    }
+}
+
+/// Loads the PNGs in a directory, or matching a pattern with a single `*`,
+/// ordered by the last number in their names.
+fn sequence(path: &Path, binarize: &Binarize) -> Result<Imported> {
+    let (dir, pattern) = match path.is_dir() {
+        true => (path, "*.png".to_owned()),
+        false => (
+            path.parent().context("Pattern has no directory")?,
+            path.file_name().unwrap().to_string_lossy().into_owned(),
+        ),
+    };
+
+    let (prefix, suffix) = pattern.split_once('*').unwrap();
+    ensure!(!suffix.contains('*'), "Patterns can only contain one `*`");
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        if path.is_file()
+            && name.len() >= prefix.len() + suffix.len()
+            && name.starts_with(prefix)
+            && name.ends_with(suffix)
+        {
+            paths.push(path);
+        }
+    }
+
+    paths.sort_by_cached_key(|path| (number(path), path.clone()));
+    images(&paths, binarize)
+}
+
+fn images(paths: &[PathBuf], binarize: &Binarize) -> Result<Imported> {
+    ensure!(!paths.is_empty(), "No images found");
+
+    let mut frames = Vec::<Image>::with_capacity(paths.len());
+    for path in paths {
+        let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+        let colors = palette_colors(path, binarize)?;
+        let frame = binarize.apply(&image.to_rgba8(), &colors);
+        if let Some(first) = frames.first()
+            && first.size != frame.size
+        {
+            bail!(
+                "{} is {}x{}, but the frames before it are {}x{}",
+                path.display(),
+                frame.size.x,
+                frame.size.y,
+                first.size.x,
+                first.size.y
+            );
+        }
+        frames.push(frame);
+    }
+
+    Ok(Imported {
+        frames,
+        delays: None,
+    })
+}
+
+/// The last run of digits in the file name, so `frame-2.png` comes before
+/// `frame-10.png`.
+fn number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let end = stem.rfind(|x: char| x.is_ascii_digit())? + 1;
+    let start = stem[..end]
+        .rfind(|x: char| !x.is_ascii_digit())
+        .map_or(0, |x| x + 1);
+    stem[start..end].parse().ok()
+}
+
+/// Colors of the palette entries selected in `palette` mode, empty in every
+/// other mode.
+fn palette_colors(path: &Path, binarize: &Binarize) -> Result<Vec<[u8; 3]>> {
+    if binarize.mode != BinarizeMode::Palette {
+        return Ok(Vec::new());
+    }
+
+    let file = BufReader::new(File::open(path)?);
+    let palette = match ImageReader::open(path)?.with_guessed_format()?.format() {
+        Some(ImageFormat::Png) => png::Decoder::new(file)
+            .read_info()?
+            .info()
+            .palette
+            .as_ref()
+            .map(|x| x.to_vec()),
+        Some(ImageFormat::Gif) => {
+            let decoder = gif::DecodeOptions::new().read_info(file)?;
+            decoder.global_palette().map(|x| x.to_vec())
+        }
+        _ => None,
+    };
+
+    let palette = palette.with_context(|| format!("{} has no palette", path.display()))?;
+    let colors = palette
+        .chunks_exact(3)
+        .map(|x| [x[0], x[1], x[2]])
+        .collect::<Vec<_>>();
+    (binarize.indices.iter())
+        .map(|&i| {
+            let color = colors.get(i);
+            color.copied().with_context(|| {
+                format!(
+                    "Palette of {} has no index {i}, only {}",
+                    path.display(),
+                    colors.len()
+                )
+            })
+        })
+        .collect()
+}
@@ -1,44 +1,66 @@
 use std::{
     env,
     fs::{self, File},
-    path::Path,
+    iter,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::Duration,
 };
 
-use anyhow::{Context, Result};
-use bitvec::{order::Lsb0, vec::BitVec};
+use anyhow::{Context, Result, ensure};
 use clap::Parser;
-use image::{GenericImageView, ImageReader};
+use image::ImageReader;
+use memmap2::Mmap;
 use nalgebra::Vector2;
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use serde::{Deserialize, Serialize};
+use wgpu::Limits;
 
 use crate::animation::{
     colormap::Colormap,
     config::AnimationConfig,
+    frames::Frames,
+    playlist::{Playlist, Timer},
     properties::{OptionalProperties, Properties},
-    timeline::PropertiesTimeline,
+    timeline::{FrameTiming, PropertiesTimeline},
+    transition::Transition,
 };
 
+pub mod binarize;
+pub mod bundle;
 pub mod colormap;
 pub mod config;
+pub mod ease;
+pub mod frames;
+pub mod import;
+pub mod playlist;
 pub mod properties;
 pub mod timeline;
+pub mod transition;
+
+/// Seconds before a scene starts that its frames start being decompressed.
+const PREFETCH: f32 = 2.0;
+/// Decompressed frames that haven't been drawn for this long are dropped.
+const EVICT_AFTER: Duration = Duration::from_secs(5);
 
 #[derive(Serialize, Deserialize)]
 pub struct Animation {
     pub colormap: Colormap,
     pub scenes: Vec<SceneData>,
     pub defaults: Properties,
+    pub playlist: Playlist,
 
+    /// Playback used by [`Animation::frame`], seeded from the runtime config.
     #[serde(skip)]
-    pub scene_timer: Timer,
+    pub playback: Playback,
     #[serde(skip)]
     pub keyframe: usize,
     #[serde(skip)]
     pub runtime: RuntimeConfig,
 }
 
-#[derive(Parser, Default)]
+#[derive(Parser)]
 #[cfg_attr(windows, command(ignore_errors = true))]
 pub struct RuntimeConfig {
     #[arg(long, default_value_t = 0.0)]
@@ -53,20 +75,101 @@ pub struct RuntimeConfig {
     pub preview: Option<isize>,
     #[arg(long, alias = "c", hide = true)]
     pub configure: bool,
-}
-
-#[derive(Default)]
-pub struct Timer {
-    index: usize,
-    offset: f32,
+    /// Multiplier applied to the speed of the animation.
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f32,
+    /// Seed for the starting scene and colormap offset, random if unset.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Seconds to cycle through the colormap, overriding the bundle.
+    #[arg(long)]
+    pub colormap_duration: Option<f32>,
+    /// Fixed starting point (0-1) in the colormap cycle, overriding the bundle.
+    #[arg(long)]
+    pub colormap_phase: Option<f32>,
+    /// Scene order, one of sequential, shuffle, weighted or a comma separated
+    /// list of scene indices.
+    #[arg(long)]
+    pub playlist: Option<Playlist>,
+    /// Compiled bundle or config.toml (or a directory containing one) to use
+    /// in place of the embedded animation.
+    #[arg(long)]
+    pub animation: Option<PathBuf>,
+    /// Upper limit on how many frames are drawn per second.
+    #[arg(long)]
+    pub max_fps: Option<f32>,
+    /// Lower the frame rate while the scene is changing slowly.
+    #[arg(long)]
+    pub adaptive: bool,
+    /// Play different scenes with different colors on each output.
+    #[arg(long)]
+    pub independent_outputs: bool,
+    /// Keep the colormap in sync across independent outputs.
+    #[arg(long, requires = "independent_outputs")]
+    pub sync_colormap: bool,
+    /// Stretch one scene across every output, following their layout.
+    #[arg(long, conflicts_with = "independent_outputs")]
+    pub span_outputs: bool,
+    /// Lock the session until the user's password is entered (Wayland only,
+    /// needs the `lock` feature).
+    #[arg(long)]
+    pub lock: bool,
+    /// Fade out and exit on keyboard or pointer input (Wayland only).
+    #[arg(long, conflicts_with = "lock")]
+    pub exit_on_input: bool,
+    /// Keep running in the background, showing the screensaver after this
+    /// many seconds idle and hiding it again on input (Wayland only).
+    #[arg(long, conflicts_with = "lock")]
+    pub idle: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SceneData {
-    pub frames: Vec<Image>,
+    /// Stored separately in bundles, see [`bundle`].
+    #[serde(skip)]
+    pub frames: Frames,
     pub duration: f32,
+    pub weight: f32,
+    pub transition: Option<Transition>,
     pub properties: OptionalProperties,
     pub timeline: PropertiesTimeline,
+    /// Delays of an animated image, driving `frame` unless the timeline
+    /// does.
+    pub frame_timing: Option<FrameTiming>,
+}
+
+pub struct Frame {
+    pub scene: SceneFrame,
+    pub transition: Option<Outgoing>,
+}
+
+/// The scene being replaced during a transition.
+pub struct Outgoing {
+    pub transition: Transition,
+    /// How far through the transition we are, from 0 to 1.
+    pub progress: f32,
+    pub scene: SceneFrame,
+}
+
+/// The state of a single scene at some point in time.
+pub struct SceneFrame {
+    /// Index of the scene in [`Animation::scenes`].
+    pub index: usize,
+    pub properties: Properties,
+    pub frames: Arc<[Image]>,
+    /// Index of the current image in `frames`.
+    pub frame: usize,
+}
+
+/// Where one viewer is in an animation, so several outputs can play
+/// different scenes from the same animation.
+#[derive(Clone, Default)]
+pub struct Playback {
+    pub scene_timer: Timer,
+    /// Starting point (0-1) in the colormap cycle.
+    pub colormap_offset: f32,
+    /// Overrides [`RuntimeConfig::fade_out`] once an exit has been requested.
+    pub fade_out: Option<f32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -77,9 +180,8 @@ pub struct Image {
 
 impl Animation {
     pub fn load(data: &[u8]) -> Result<Self> {
-        let mut this =
-            bincode::serde::decode_from_slice::<Self, _>(data, bincode::config::standard())?.0;
-        this.scene_timer = Timer::new(this.scenes());
+        let mut this = bundle::decode(Arc::new(data.to_vec()))?;
+        this.playback = this.new_playback(&mut rand::rng());
         Ok(this)
     }
 
@@ -93,101 +195,248 @@ impl Animation {
         let background = ImageReader::open(dir.join(&config.background.colormap))?
             .with_guessed_format()?
             .decode()?;
-        let colormap = Colormap::new(background);
+        ensure!(
+            config.background.duration > 0.0,
+            "The colormap duration must be positive"
+        );
+        let colormap = Colormap::new(
+            background,
+            config.background.duration,
+            config.background.phase,
+        );
 
         let mut scenes = Vec::with_capacity(config.scenes.scene.len());
         for scene in config.scenes.scene {
-            let mut frames = Vec::new();
-            let image = ImageReader::open(dir.join(&scene.image))?
-                .with_guessed_format()?
-                .decode()?;
-
-            let height = image.height() / scene.frames;
-            for frame in 0..scene.frames {
-                let mut buffer = BitVec::<u32, Lsb0>::new();
-                for y in (height * frame)..(height * (frame + 1)) {
-                    for x in 0..image.width() {
-                        let pixel = image.get_pixel(x, y);
-                        let active = pixel[0] != 0 && pixel[1] != 0 && pixel[2] != 0;
-                        buffer.push(active);
-                    }
-                }
-
-                frames.push(Image {
-                    data: buffer.into_vec(),
-                    size: Vector2::new(image.width(), height),
-                });
-            }
+            let imported = import::load(dir, &scene)?;
+            let size = imported.frames.iter().map(|x| x.byte_size()).sum::<u64>();
+            let limit = Limits::default().max_storage_buffer_binding_size as u64;
+            ensure!(
+                size <= limit,
+                "The {} frames of {} need {size} bytes but GPUs are only guaranteed to support {limit}",
+                imported.frames.len(),
+                scene.image.display(),
+            );
 
+            ensure!(scene.duration > 0.0, "Scene durations must be positive");
             scenes.push(SceneData {
-                frames,
+                frames: Frames::new(imported.frames),
                 duration: scene.duration,
+                weight: scene.weight,
+                transition: scene.transition,
                 properties: scene.properties,
-                timeline: PropertiesTimeline::new(&scene.keyframes),
+                timeline: PropertiesTimeline::new(&scene.keyframes, scene.curve),
+                frame_timing: imported.delays.as_deref().and_then(FrameTiming::new),
             });
         }
 
-        Ok(Self {
-            scene_timer: Timer::new(scenes.len()),
+        let playlist = config.scenes.playlist;
+        playlist.validate(scenes.len())?;
+
+        let mut this = Self {
+            playback: Playback::default(),
             keyframe: 0,
 
             colormap,
             scenes,
             defaults: config.scenes.properties,
+            playlist,
             runtime: RuntimeConfig::default(),
-        })
+        };
+        this.playback = this.new_playback(&mut rand::rng());
+        Ok(this)
     }
 
-    pub fn runtime_from_args(self) -> Self {
-        let args = env::args().flat_map(|x| {
-            if let Some(arg) = x.strip_prefix('/') {
-                if let Some((key, value)) = arg.split_once(':') {
-                    vec![format!("--{key}"), value.to_string()]
-                } else {
-                    vec![format!("--{arg}")]
-                }
-            } else {
-                vec![x]
-            }
-        });
+    /// Loads a compiled bundle, a config.toml, or a directory containing a
+    /// config.toml.
+    pub fn load_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Self::load_dev(path.join("config.toml"));
+        }
 
-        Self {
-            runtime: RuntimeConfig::parse_from(args),
-            ..self
+        if path.extension().is_some_and(|x| x == "toml") {
+            Self::load_dev(path)
+        } else {
+            Self::load_mapped(path)
+        }
+    }
+
+    /// Loads a bundle without reading it into memory, so scenes are only read
+    /// from disk once they're about to be drawn.
+    pub fn load_mapped(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+        // Safety: the bundle must not be modified while mapped. `export`
+        // replaces bundles instead of writing over them, so rebuilding one
+        // leaves this mapping on the old file
+        let map = unsafe { Mmap::map(&file)? };
+        let mut this = bundle::decode(Arc::new(map))?;
+        this.playback = this.new_playback(&mut rand::rng());
+        Ok(this)
+    }
+
+    /// Loads the animation requested by `runtime.animation`, falling back to
+    /// the embedded bundle.
+    pub fn load_runtime(embedded: &[u8], runtime: RuntimeConfig) -> Result<Self> {
+        let this = match &runtime.animation {
+            Some(path) => Self::load_path(path)?,
+            None => Self::load(embedded)?,
+        };
+
+        this.with_runtime(runtime)
+    }
+
+    pub fn runtime_from_args(self) -> Result<Self> {
+        self.with_runtime(RuntimeConfig::from_args())
+    }
+
+    pub fn with_runtime(self, runtime: RuntimeConfig) -> Result<Self> {
+        let mut this = Self { runtime, ..self };
+        if let Some(playlist) = &this.runtime.playlist {
+            playlist.validate(this.scenes.len())?;
+            this.playlist = playlist.clone();
+        }
+
+        // Kept so playbacks for other outputs can be derived from it
+        let seed = *(this.runtime.seed).get_or_insert_with(|| rand::rng().random());
+        this.seed(seed);
+
+        let rt = &this.runtime;
+        if let Some(duration) = rt.colormap_duration {
+            ensure!(duration > 0.0, "The colormap duration must be positive");
+            this.colormap.duration = duration;
+        }
+        this.colormap.phase = rt.colormap_phase.or(this.colormap.phase);
+
+        Ok(this)
+    }
+
+    /// Re-rolls the starting scene and colormap offset from `seed`, so the
+    /// same seed and time always give the same frame.
+    pub fn seed(&mut self, seed: u64) {
+        self.playback = self.new_playback(&mut StdRng::seed_from_u64(seed));
+    }
+
+    /// Creates the playback for one of several independent outputs, which
+    /// differs between outputs but is still the same for the same seed.
+    pub fn output_playback(&self, output: usize) -> Playback {
+        let seed = self.runtime.seed.unwrap_or_default() ^ output as u64;
+        self.new_playback(&mut StdRng::seed_from_u64(seed))
+    }
+
+    /// Creates a playback with a random starting scene and colormap offset.
+    pub fn new_playback(&self, rng: &mut impl Rng) -> Playback {
+        Playback {
+            scene_timer: Timer::new(rng, &self.playlist, &self.scenes),
+            colormap_offset: rng.random(),
+            fade_out: None,
         }
     }
 
-    pub fn with_runtime(self, runtime: RuntimeConfig) -> Self {
-        Self { runtime, ..self }
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bundle::encode(self)
     }
 
+    /// Writes the bundle next to `path` and renames it over the old one, so
+    /// processes that have the old bundle mapped keep reading it instead of
+    /// a file being rewritten under them.
     pub fn export(&self, path: impl AsRef<Path>) -> Result<()> {
-        bincode::serde::encode_into_std_write(
-            self,
-            &mut File::create(path)?,
-            bincode::config::standard(),
-        )?;
-        Ok(())
+        let path = path.as_ref();
+        let name = path.file_name().context("Path must be a file")?;
+        let temp =
+            path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), process::id()));
+
+        let result = fs::write(&temp, self.encode()?).and_then(|()| fs::rename(&temp, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+
+        result.with_context(|| format!("Failed to write {}", path.display()))
     }
 }
 
 impl Animation {
-    pub fn scene(&mut self, time: f32) -> (Properties, &Image) {
-        let t = time - self.scene_timer.offset;
-        let scene = &self.scenes[self.scene_timer.index];
-
-        if t > scene.duration {
-            self.scene_timer.offset = time;
-            self.scene_timer.index = (self.scene_timer.index + 1) % self.scenes.len();
-            self.keyframe = 0;
+    pub fn scene(&mut self, time: f32) -> SceneFrame {
+        self.frame(time).scene
+    }
+
+    /// Finds what to draw at `time`, including the outgoing scene if a
+    /// transition is in progress.
+    pub fn frame(&mut self, time: f32) -> Frame {
+        let timer = &mut self.playback.scene_timer;
+        timer.advance(&self.playlist, &self.scenes, time);
+        self.locate(&self.playback, time)
+    }
+
+    /// Like [`Animation::frame`], but following `playback` instead.
+    pub fn frame_with(&self, playback: &mut Playback, time: f32) -> Frame {
+        let timer = &mut playback.scene_timer;
+        timer.advance(&self.playlist, &self.scenes, time);
+        self.locate(playback, time)
+    }
+
+    fn locate(&self, playback: &Playback, time: f32) -> Frame {
+        let timer = &playback.scene_timer;
+        self.manage_frames(timer, time);
+
+        let (index, t) = timer.current(time);
+        let scene = self.sample(index, t);
+
+        let transition = self.scenes[index]
+            .transition
+            .filter(|x| t < x.duration)
+            .zip(timer.previous(time))
+            .map(|(transition, (previous, t_previous))| Outgoing {
+                transition,
+                progress: t / transition.duration,
+                scene: self.sample(previous, t_previous),
+            });
+
+        Frame { scene, transition }
+    }
+
+    /// Prefetches the frames of the next scene and evicts the ones that are
+    /// no longer being drawn.
+    fn manage_frames(&self, timer: &Timer, time: f32) {
+        let upcoming = timer.upcoming(&self.playlist, &self.scenes, time + PREFETCH);
+        self.scenes[upcoming].frames.prefetch();
+        for scene in &self.scenes {
+            scene.frames.evict(EVICT_AFTER);
+        }
+    }
+
+    fn sample(&self, index: usize, t: f32) -> SceneFrame {
+        let scene = &self.scenes[index];
+
+        let mut animated = scene.timeline.get(t);
+        if let Some(timing) = &scene.frame_timing {
+            animated.frame = animated.frame.or(Some(timing.frame(t)));
         }
 
-        let animated = scene.timeline.get(t);
         let properties = animated
             .combine(&scene.properties)
             .with_defaults(&self.defaults);
-        let frame = &scene.frames[properties.frame % scene.frames.len()];
-        (properties, frame)
+        SceneFrame {
+            index,
+            frame: properties.frame % scene.frames.len(),
+            properties,
+            frames: scene.frames.load_or_blank(),
+        }
+    }
+
+    /// Jumps to the scene at `index`, treating `time` as its start.
+    pub fn set_scene(&mut self, index: usize, time: f32) {
+        let index = index % self.scenes.len();
+        (self.playback.scene_timer).set(&self.playlist, index, time);
+        self.keyframe = 0;
+    }
+
+    /// Size in bytes of the bitmaps of the largest scene.
+    pub fn max_scene_size(&self) -> u64 {
+        let scenes = self.scenes.iter();
+        scenes.map(|x| x.byte_size()).max().unwrap_or_default()
     }
 
     pub fn scenes(&self) -> usize {
@@ -197,18 +446,66 @@ impl Animation {
     pub fn frames(&self, n: usize) -> usize {
         self.scenes[n].frames.len()
     }
+}
+
+impl SceneData {
+    /// Size in bytes of the bitmaps of every frame.
+    pub fn byte_size(&self) -> u64 {
+        self.frames.byte_size()
+    }
+}
 
-    pub fn image(&self, n: usize, frame: usize) -> &Image {
-        &self.scenes[n].frames[frame]
+impl SceneFrame {
+    pub fn image(&self) -> &Image {
+        &self.frames[self.frame]
     }
 }
 
-impl Timer {
-    fn new(max: usize) -> Self {
-        let mut rng = rand::rng();
-        Timer {
-            index: rng.random_range(0..max),
-            offset: 0.0,
+impl Image {
+    /// Size in bytes of a bitmap with `size` pixels, one bit per pixel rounded
+    /// up to whole words.
+    pub fn byte_size_for(size: Vector2<u32>) -> u64 {
+        (size.x as u64 * size.y as u64).div_ceil(32) * 4
+    }
+
+    pub fn byte_size(&self) -> u64 {
+        size_of_val(self.data.as_slice()) as u64
+    }
+
+    /// A single pixel that isn't drawn.
+    pub fn blank() -> Self {
+        Self {
+            data: vec![u32::MAX],
+            size: Vector2::new(1, 1),
         }
     }
 }
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self::parse_from(iter::empty::<String>())
+    }
+}
+
+impl RuntimeConfig {
+    pub fn from_args() -> Self {
+        // Windows passes screensaver switches as `/s`, `/p:1234` or `/c`,
+        // elsewhere arguments starting with a slash are absolute paths
+        #[cfg(not(windows))]
+        let args = env::args();
+        #[cfg(windows)]
+        let args = env::args().flat_map(|x| {
+            if let Some(arg) = x.strip_prefix('/') {
+                if let Some((key, value)) = arg.split_once(':') {
+                    vec![format!("--{key}"), value.to_string()]
+                } else {
+                    vec![format!("--{arg}")]
+                }
+            } else {
+                vec![x]
+            }
+        });
+
+        Self::parse_from(args)
+    }
+}
@@ -0,0 +1,318 @@
+use std::str::FromStr;
+
+use anyhow::{Result, ensure};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::SceneData;
+
+/// Scenes older than this are forgotten, so a long running screensaver
+/// doesn't slowly fill up memory.
+const MAX_HISTORY: usize = 1024;
+
+/// Order in which scenes are played.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Playlist {
+    /// Every scene in config order, starting from a random one.
+    #[default]
+    Sequential,
+    /// Every scene once in a random order before any repeats.
+    Shuffle,
+    /// Random scenes picked by their `weight`, never the same twice in a row.
+    Weighted,
+    /// Loops over the listed scene indices.
+    Fixed(Vec<usize>),
+}
+
+impl Playlist {
+    /// Makes sure a fixed playlist only refers to the first `scenes` scenes.
+    pub fn validate(&self, scenes: usize) -> Result<()> {
+        if let Playlist::Fixed(order) = self {
+            let invalid = order.iter().find(|&&x| x >= scenes);
+            ensure!(
+                invalid.is_none(),
+                "Playlist refers to scene #{} which does not exist",
+                invalid.unwrap()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Tracks which scenes have played and when, so the scene at any point in
+/// time can be looked up even when the clock is seeked.
+#[derive(Clone)]
+pub struct Timer {
+    rng: StdRng,
+    offset: f32,
+    history: Vec<Entry>,
+    /// Position in a fixed playlist.
+    position: usize,
+    /// Scenes left to play in this round of a shuffled playlist.
+    bag: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    scene: usize,
+    start: f32,
+}
+
+impl Timer {
+    pub fn new(rng: &mut impl Rng, playlist: &Playlist, scenes: &[SceneData]) -> Self {
+        let mut this = Self {
+            rng: StdRng::from_rng(rng),
+            history: Vec::new(),
+            ..Self::default()
+        };
+
+        let first = match playlist {
+            Playlist::Sequential => this.rng.random_range(0..scenes.len()),
+            Playlist::Fixed(order) if !order.is_empty() => {
+                this.position = this.rng.random_range(0..order.len());
+                order[this.position] % scenes.len()
+            }
+            _ => this.next(playlist, scenes),
+        };
+
+        this.history.push(Entry {
+            scene: first,
+            start: 0.0,
+        });
+        this
+    }
+
+    /// Restarts the playlist from `scene` at `time`.
+    pub fn set(&mut self, playlist: &Playlist, scene: usize, time: f32) {
+        if let Playlist::Fixed(order) = playlist {
+            self.position = order.iter().position(|&x| x == scene).unwrap_or_default();
+        }
+
+        self.offset = time;
+        self.bag.clear();
+        self.history = vec![Entry { scene, start: 0.0 }];
+    }
+
+    /// Picks new scenes from the playlist until the history reaches `time`.
+    pub fn advance(&mut self, playlist: &Playlist, scenes: &[SceneData], time: f32) {
+        let t = time - self.offset;
+        while let Some(last) = self.history.last()
+            && last.start + scenes[last.scene].duration <= t
+        {
+            let start = last.start + scenes[last.scene].duration;
+            let scene = self.next(playlist, scenes);
+            self.history.push(Entry { scene, start });
+
+            // Trimmed as it goes, so seeking far ahead doesn't build up a
+            // huge history first
+            let len = self.history.len();
+            if len > MAX_HISTORY {
+                self.history.drain(..len - MAX_HISTORY / 2);
+            }
+        }
+    }
+
+    /// Finds the scene playing at `time` and how far into it we are. Must be
+    /// called after [`Timer::advance`].
+    pub fn current(&self, time: f32) -> (usize, f32) {
+        let t = time - self.offset;
+        let index = self.history.partition_point(|x| x.start <= t).max(1) - 1;
+        let entry = &self.history[index];
+        (entry.scene, (t - entry.start).max(0.0))
+    }
+
+    /// The scene played before the one at `time`, and how far into it `time`
+    /// would be. Must be called after [`Timer::advance`].
+    pub fn previous(&self, time: f32) -> Option<(usize, f32)> {
+        let t = time - self.offset;
+        let index = self.history.partition_point(|x| x.start <= t);
+        let entry = self.history.get(index.checked_sub(2)?)?;
+        Some((entry.scene, t - entry.start))
+    }
+
+    /// The scene that will be playing at `time`, without advancing this
+    /// timer.
+    pub fn upcoming(&self, playlist: &Playlist, scenes: &[SceneData], time: f32) -> usize {
+        let mut timer = self.clone();
+        timer.advance(playlist, scenes, time);
+        timer.current(time).0
+    }
+
+    fn next(&mut self, playlist: &Playlist, scenes: &[SceneData]) -> usize {
+        let count = scenes.len();
+        let last = self.history.last().map(|x| x.scene);
+
+        match playlist {
+            Playlist::Fixed(order) if !order.is_empty() => {
+                self.position = (self.position + 1) % order.len();
+                order[self.position] % count
+            }
+            Playlist::Sequential | Playlist::Fixed(_) => last.map_or(0, |x| (x + 1) % count),
+            Playlist::Shuffle => {
+                if self.bag.is_empty() {
+                    self.bag = (0..count).collect();
+                    self.bag.shuffle(&mut self.rng);
+
+                    // Don't repeat the last scene across the end of a round
+                    if count > 1 && self.bag.last() == last.as_ref() {
+                        self.bag.swap(0, count - 1);
+                    }
+                }
+
+                self.bag.pop().unwrap()
+            }
+            Playlist::Weighted => {
+                let weight = |i: usize| {
+                    if Some(i) == last && count > 1 {
+                        0.0
+                    } else {
+                        scenes[i].weight.max(0.0)
+                    }
+                };
+
+                let total = (0..count).map(weight).sum::<f32>();
+                if total <= 0.0 {
+                    return last.map_or(0, |x| (x + 1) % count);
+                }
+
+                let mut pick = self.rng.random::<f32>() * total;
+                for i in 0..count {
+                    pick -= weight(i);
+                    if pick < 0.0 {
+                        return i;
+                    }
+                }
+
+                (0..count).rev().find(|&i| weight(i) > 0.0).unwrap()
+            }
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(0),
+            offset: 0.0,
+            history: vec![Entry {
+                scene: 0,
+                start: 0.0,
+            }],
+            position: 0,
+            bag: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Playlist {
+    type Err = String;
+
+    /// Parses `sequential`, `shuffle`, `weighted` or a comma separated list of
+    /// scene indices for a fixed playlist.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sequential" => Playlist::Sequential,
+            "shuffle" => Playlist::Shuffle,
+            "weighted" => Playlist::Weighted,
+            _ => Playlist::Fixed(
+                s.split(',')
+                    .map(|x| x.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("Invalid playlist `{s}`"))?,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenes(weights: &[f32]) -> Vec<SceneData> {
+        (weights.iter())
+            .map(|&weight| SceneData {
+                frames: Default::default(),
+                duration: 1.0,
+                weight,
+                transition: None,
+                properties: Default::default(),
+                timeline: Default::default(),
+                frame_timing: None,
+            })
+            .collect()
+    }
+
+    /// The scenes a playlist plays over its first `count` seconds.
+    fn play(playlist: &Playlist, scenes: &[SceneData], seed: u64, count: usize) -> Vec<usize> {
+        let mut timer = Timer::new(&mut StdRng::seed_from_u64(seed), playlist, scenes);
+        (0..count)
+            .map(|i| {
+                let t = i as f32 + 0.5;
+                timer.advance(playlist, scenes, t);
+                timer.current(t).0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shuffle_plays_every_scene_without_repeats() {
+        let scenes = scenes(&[1.0; 4]);
+        for seed in 0..32 {
+            let played = play(&Playlist::Shuffle, &scenes, seed, 40);
+            assert!(played.windows(2).all(|x| x[0] != x[1]), "{played:?}");
+            for round in played.chunks(4) {
+                let mut round = round.to_vec();
+                round.sort();
+                assert_eq!(round, [0, 1, 2, 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_skips_zero_weights() {
+        let scenes = scenes(&[0.0, 2.0, 0.0, 1.0]);
+        for seed in 0..32 {
+            let played = play(&Playlist::Weighted, &scenes, seed, 40);
+            assert!(played.iter().all(|&x| x == 1 || x == 3), "{played:?}");
+            assert!(played.windows(2).all(|x| x[0] != x[1]), "{played:?}");
+        }
+    }
+
+    #[test]
+    fn weighted_falls_back_to_sequential_without_weights() {
+        let played = play(&Playlist::Weighted, &scenes(&[0.0; 3]), 0, 5);
+        assert_eq!(played, [0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn history_stays_bounded_after_seeking() {
+        let (playlist, scenes) = (Playlist::Shuffle, scenes(&[1.0; 3]));
+        let mut timer = Timer::new(&mut StdRng::seed_from_u64(0), &playlist, &scenes);
+        timer.advance(&playlist, &scenes, 100_000.5);
+        assert!(timer.history.len() <= MAX_HISTORY);
+        assert!(timer.previous(100_000.5).is_some());
+    }
+
+    #[test]
+    fn fixed_rejects_missing_scenes() {
+        assert!(Playlist::Fixed(vec![0, 2]).validate(3).is_ok());
+        assert!(Playlist::Fixed(vec![0, 3]).validate(3).is_err());
+    }
+
+    #[test]
+    fn fixed_wraps_around() {
+        let order = [2, 0, 2, 1];
+        for seed in 0..8 {
+            let played = play(
+                &Playlist::Fixed(order.to_vec()),
+                &scenes(&[1.0; 3]),
+                seed,
+                12,
+            );
+            let wraps = |start: usize| (0..12).all(|i| played[i] == order[(start + i) % 4]);
+            assert!((0..4).any(wraps), "{played:?}");
+        }
+    }
+}
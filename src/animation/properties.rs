@@ -1,4 +1,4 @@
-use nalgebra::{Matrix4, Vector3};
+use nalgebra::{Matrix4, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
 use crate::interpolate::Interpolate;
@@ -23,6 +23,15 @@ pub struct Properties {
     pub progress_angle: f32,
 }
 
+/// The part of a canvas shown on one target, so a scene can be spread across
+/// several monitors. Measured in any unit with y going down.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    pub canvas: Vector2<f32>,
+    pub offset: Vector2<f32>,
+    pub size: Vector2<f32>,
+}
+
 impl OptionalProperties {
     pub fn combine(&self, other: &Self) -> Self {
         Self {
@@ -73,7 +82,8 @@ impl Properties {
         }
     }
 
-    pub fn view_projection(&self, aspect: f32) -> Matrix4<f32> {
+    pub fn view_projection(&self, viewport: &Viewport) -> Matrix4<f32> {
+        let aspect = viewport.canvas.x / viewport.canvas.y;
         let depth = 100.0;
         let projection = if aspect < 1.0 {
             Matrix4::new_orthographic(-aspect, aspect, -1.0, 1.0, -depth, depth)
@@ -88,6 +98,33 @@ impl Properties {
             &Vector3::z_axis(),
         );
 
-        projection * view * scale
+        viewport.crop() * projection * view * scale
+    }
+}
+
+impl Viewport {
+    /// The whole of a canvas of `size`.
+    pub fn full(size: Vector2<f32>) -> Self {
+        Self {
+            canvas: size,
+            offset: Vector2::zeros(),
+            size,
+        }
+    }
+
+    /// Top left and bottom right corners of the viewport, from 0 to 1.
+    pub fn bounds(&self) -> (Vector2<f32>, Vector2<f32>) {
+        let min = self.offset.component_div(&self.canvas);
+        (min, min + self.size.component_div(&self.canvas))
+    }
+
+    /// Maps the viewport's part of clip space onto the whole of it.
+    fn crop(&self) -> Matrix4<f32> {
+        let (min, max) = self.bounds();
+        let scale = (max - min).map(|x| x.recip());
+        // Clip space has y going up
+        let center = Vector2::new(min.x + max.x - 1.0, 1.0 - min.y - max.y);
+
+        Matrix4::new_nonuniform_scaling(&scale.push(1.0)).prepend_translation(&-center.push(0.0))
     }
 }
@@ -3,7 +3,7 @@ use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    animation::{config::PropertyKeyframe, properties::OptionalProperties},
+    animation::{config::PropertyKeyframe, ease::Ease, properties::OptionalProperties},
     interpolate::Interpolate,
 };
 
@@ -17,10 +17,32 @@ pub struct Timeline<T> {
 pub struct Keyframe<T> {
     pub t: f32,
     pub value: T,
+    /// Curve used when interpolating towards the next keyframe.
+    pub ease: Ease,
+}
+
+/// How the camera moves between `camera_pos` and `camera_dir` keyframes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Curve {
+    /// Straight lines between keyframes.
+    #[default]
+    Linear,
+    /// A smooth path through every keyframe.
+    CatmullRom,
+}
+
+/// Timing of the frames of an animated image, looping through them with
+/// their own delays.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrameTiming {
+    /// Time each frame ends at, relative to the start of the loop.
+    ends: Vec<f32>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct PropertiesTimeline {
+    curve: Curve,
     camera_pos: Timeline<Vector3<f32>>,
     camera_dir: Timeline<Vector3<f32>>,
     scale: Timeline<f32>,
@@ -35,10 +57,19 @@ impl<T: Interpolate + Copy> Timeline<T> {
     }
 
     pub fn get(&self, t: f32) -> Option<T> {
+        self.sample(t, |i, frac| {
+            let (last, next) = (&self.keyframes[i - 1], &self.keyframes[i]);
+            last.value.interpolate(&next.value, frac)
+        })
+    }
+
+    /// Finds the keyframes surrounding `t` and calls `interpolate` with the
+    /// index of the next keyframe and the eased progress towards it.
+    fn sample(&self, t: f32, interpolate: impl Fn(usize, f32) -> T) -> Option<T> {
         for i in 0..self.keyframes.len() {
             let keyframe = &self.keyframes[i];
             if t < keyframe.t {
-                let Some(last) = self.keyframes.get(i - 1) else {
+                let Some(last) = i.checked_sub(1).and_then(|i| self.keyframes.get(i)) else {
                     return Some(keyframe.value);
                 };
 
@@ -46,7 +77,7 @@ impl<T: Interpolate + Copy> Timeline<T> {
                 let out = if frac.is_nan() {
                     keyframe.value
                 } else {
-                    last.value.interpolate(&keyframe.value, frac)
+                    interpolate(i, last.ease.apply(frac))
                 };
 
                 return Some(out);
@@ -57,37 +88,104 @@ impl<T: Interpolate + Copy> Timeline<T> {
     }
 }
 
+impl Timeline<Vector3<f32>> {
+    /// Interpolates along a Catmull-Rom spline through every keyframe, so the
+    /// velocity is continuous across keyframes.
+    pub fn get_spline(&self, t: f32) -> Option<Vector3<f32>> {
+        self.sample(t, |i, frac| {
+            let (last, next) = (&self.keyframes[i - 1], &self.keyframes[i]);
+            let dt = next.t - last.t;
+
+            let m0 = self.tangent(i - 1) * dt;
+            let m1 = self.tangent(i) * dt;
+
+            let (t2, t3) = (frac * frac, frac * frac * frac);
+            last.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + m0 * (t3 - 2.0 * t2 + frac)
+                + next.value * (-2.0 * t3 + 3.0 * t2)
+                + m1 * (t3 - t2)
+        })
+    }
+
+    /// Velocity at a keyframe, from the keyframes on either side of it (or
+    /// just the one neighbor at the ends of the timeline).
+    fn tangent(&self, i: usize) -> Vector3<f32> {
+        let before = &self.keyframes[i.saturating_sub(1)];
+        let after = &self.keyframes[(i + 1).min(self.keyframes.len() - 1)];
+
+        let dt = after.t - before.t;
+        if dt <= 0.0 {
+            return Vector3::zeros();
+        }
+
+        (after.value - before.value) / dt
+    }
+}
+
+impl FrameTiming {
+    /// Timing from the delay of each frame, `None` if they don't add up to
+    /// any time.
+    pub fn new(delays: &[f32]) -> Option<Self> {
+        let ends = (delays.iter())
+            .scan(0.0, |end, delay| {
+                *end += delay.max(0.0);
+                Some(*end)
+            })
+            .collect::<Vec<_>>();
+        (ends.last().is_some_and(|&x| x > 0.0)).then_some(Self { ends })
+    }
+
+    /// The frame shown `t` seconds into the scene.
+    pub fn frame(&self, t: f32) -> usize {
+        let t = t.rem_euclid(*self.ends.last().unwrap());
+        let frame = self.ends.partition_point(|&end| end <= t);
+        frame.min(self.ends.len() - 1)
+    }
+}
+
 impl PropertiesTimeline {
-    pub fn new(keyframes: &[PropertyKeyframe]) -> Self {
-        let mut timeline = Self::default();
+    pub fn new(keyframes: &[PropertyKeyframe], curve: Curve) -> Self {
+        let mut timeline = Self {
+            curve,
+            ..Self::default()
+        };
 
         for keyframe in keyframes {
-            let t = keyframe.t;
+            let (t, ease) = (keyframe.t, keyframe.ease);
             if let Some(value) = keyframe.properties.camera_dir {
-                timeline.camera_dir.keyframes.push(Keyframe { t, value });
+                timeline
+                    .camera_dir
+                    .keyframes
+                    .push(Keyframe { t, value, ease });
             }
 
             if let Some(value) = keyframe.properties.camera_pos {
-                timeline.camera_pos.keyframes.push(Keyframe { t, value });
+                timeline
+                    .camera_pos
+                    .keyframes
+                    .push(Keyframe { t, value, ease });
             }
 
             if let Some(value) = keyframe.properties.scale {
-                timeline.scale.keyframes.push(Keyframe { t, value });
+                timeline.scale.keyframes.push(Keyframe { t, value, ease });
             }
 
             if let Some(value) = keyframe.properties.frame {
-                timeline.frame.keyframes.push(Keyframe { t, value });
+                timeline.frame.keyframes.push(Keyframe { t, value, ease });
             }
 
             if let Some(value) = keyframe.properties.progress {
-                timeline.progress.keyframes.push(Keyframe { t, value });
+                timeline
+                    .progress
+                    .keyframes
+                    .push(Keyframe { t, value, ease });
             }
 
             if let Some(value) = keyframe.properties.progress_angle {
                 timeline
                     .progress_angle
                     .keyframes
-                    .push(Keyframe { t, value });
+                    .push(Keyframe { t, value, ease });
             }
         }
 
@@ -102,9 +200,14 @@ impl PropertiesTimeline {
     }
 
     pub fn get(&self, t: f32) -> OptionalProperties {
+        let vector = |timeline: &Timeline<Vector3<f32>>| match self.curve {
+            Curve::Linear => timeline.get(t),
+            Curve::CatmullRom => timeline.get_spline(t),
+        };
+
         OptionalProperties {
-            camera_pos: self.camera_pos.get(t),
-            camera_dir: self.camera_dir.get(t),
+            camera_pos: vector(&self.camera_pos),
+            camera_dir: vector(&self.camera_dir),
             scale: self.scale.get(t),
             frame: self.frame.get(t),
             progress: self.progress.get(t),
@@ -112,3 +215,50 @@ impl PropertiesTimeline {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catmull_rom_passes_through_keyframes() {
+        let points = [
+            (0.0, Vector3::new(0.0, 0.0, 0.0)),
+            (1.0, Vector3::new(1.0, 2.0, 0.0)),
+            (1.5, Vector3::new(-3.0, 0.5, 1.0)),
+            (4.0, Vector3::new(2.0, -1.0, 5.0)),
+        ];
+        let keyframes = (points.iter())
+            .map(|&(t, camera_pos)| PropertyKeyframe {
+                t,
+                ease: Ease::EaseInOut,
+                properties: OptionalProperties {
+                    camera_pos: Some(camera_pos),
+                    ..Default::default()
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let timeline = PropertiesTimeline::new(&keyframes, Curve::CatmullRom);
+        for (t, value) in points {
+            let sampled = timeline.get(t).camera_pos.unwrap();
+            assert!((sampled - value).norm() < 1e-5, "{sampled} at {t}");
+        }
+
+        // Still a curve between them rather than a line
+        let (a, b) = (points[1].1, points[2].1);
+        let middle = timeline.get(1.25).camera_pos.unwrap();
+        assert!((middle - (a + b) / 2.0).norm() > 1e-3);
+    }
+
+    #[test]
+    fn frame_timing_loops_through_delays() {
+        let timing = FrameTiming::new(&[0.1, 0.3, 0.1]).unwrap();
+        let frames = [0.05, 0.1, 0.39, 0.45, 0.5, 0.75].map(|t| timing.frame(t));
+        assert_eq!(frames, [0, 1, 1, 2, 0, 1]);
+
+        // Long after the start, where stepping by the delays would stall
+        assert!(timing.frame(1e7) < 3);
+        assert!(FrameTiming::new(&[0.0, 0.0]).is_none());
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// How a scene replaces the one before it, over the first `duration` seconds
+/// of the scene.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub duration: f32,
+    /// Direction of a wipe in radians, zero moves left to right.
+    #[serde(default)]
+    pub angle: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransitionKind {
+    /// Fades the old scene out while fading the new one in.
+    CrossFade,
+    /// Shrinks the old scene's pixels away in a random order while the new
+    /// scene's pixels grow in.
+    Dissolve,
+    /// Sweeps the new scene across the screen along `angle`.
+    Wipe,
+}
@@ -0,0 +1,294 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result, ensure};
+use clap::{Parser, Subcommand};
+use image::RgbaImage;
+use nalgebra::Vector2;
+use wgpu::{Limits, TextureFormat};
+
+use macintosh_wallpaper::{
+    animation::{Animation, Playback, properties::Viewport},
+    clock::Clock,
+    pipelines::{Gpu, Renderer, Uniforms, offscreen::Offscreen, reference},
+    record::Recorder,
+};
+
+#[derive(Parser)]
+#[command(about = "Compile, inspect, validate and render animation bundles")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a config.toml into a binary bundle.
+    Build {
+        config: PathBuf,
+        #[arg(short, long, default_value = "animation/animation.bin")]
+        output: PathBuf,
+    },
+    /// Print a summary of the scenes in a config or bundle.
+    Inspect { path: PathBuf },
+    /// Check that a config or bundle loads and survives a round trip.
+    Validate { path: PathBuf },
+    /// Render frames of a scene to PNGs without a display.
+    Render {
+        #[command(flatten)]
+        target: TargetArgs,
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+        /// Seconds into the scene to render, can be repeated.
+        #[arg(short, long, default_values_t = [0.0])]
+        time: Vec<f32>,
+    },
+    /// Record part of the animation to a gif, apng or y4m file.
+    Record {
+        #[command(flatten)]
+        target: TargetArgs,
+        output: PathBuf,
+        /// Frames per second, at most 65535 as APNGs store it in 16 bits.
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..=u16::MAX as i64))]
+        fps: u32,
+        /// Seconds into the scene to start recording.
+        #[arg(long, default_value_t = 0.0)]
+        start: f32,
+        /// Seconds to record, defaults to the length of the scene or to every
+        /// scene with `--all`.
+        #[arg(long)]
+        duration: Option<f32>,
+        /// Record the whole cycle of scenes instead of just one.
+        #[arg(long, conflicts_with = "duration")]
+        all: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct TargetArgs {
+    path: PathBuf,
+    #[arg(long, default_value_t = 0)]
+    scene: usize,
+    #[arg(long, default_value_t = 1920)]
+    width: u32,
+    #[arg(long, default_value_t = 1080)]
+    height: u32,
+    /// Seed for the colormap offset, so renders are reproducible.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Use a software adapter (e.g. llvmpipe / lavapipe).
+    #[arg(long)]
+    fallback: bool,
+    /// Use the CPU reference rasterizer instead of the GPU.
+    #[arg(long, conflicts_with = "fallback")]
+    cpu: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Build { config, output } => {
+            let animation = Animation::load_dev(&config)?;
+            animation.export(&output)?;
+
+            let size = fs::metadata(&output)?.len();
+            println!(
+                "Wrote {} scenes to {} ({size} bytes)",
+                animation.scenes(),
+                output.display()
+            );
+        }
+        Command::Inspect { path } => inspect(&Animation::load_path(&path)?)?,
+        Command::Validate { path } => {
+            validate(&Animation::load_path(&path)?)?;
+            println!("{} is valid", path.display());
+        }
+        Command::Render {
+            target,
+            output,
+            mut time,
+        } => {
+            let scene = target.scene;
+            let mut target = Target::new(&target)?;
+
+            fs::create_dir_all(&output)?;
+            time.sort_by(f32::total_cmp);
+            for time in time {
+                let file = output.join(format!("scene-{scene}-{time:.2}.png"));
+                target.render(time).save(&file)?;
+                println!("Wrote {}", file.display());
+            }
+        }
+        Command::Record {
+            target,
+            output,
+            fps,
+            start,
+            duration,
+            all,
+        } => {
+            let mut target = Target::new(&target)?;
+            let animation = target.animation();
+            let duration = match duration {
+                Some(duration) => duration,
+                None if all => animation.scenes.iter().map(|x| x.duration).sum(),
+                None => animation.scenes[target.scene].duration,
+            };
+
+            let frames = (duration * fps as f32).ceil() as u32;
+            let mut recorder = Recorder::new(&output, target.size, fps, frames)?;
+            let mut clock = Clock::fixed_step(1.0 / fps as f32);
+            clock.seek(start);
+
+            for i in 0..frames {
+                recorder.push(target.render(clock.tick()))?;
+                print!("\rFrame {}/{frames}", i + 1);
+                io::stdout().flush()?;
+            }
+
+            recorder.finish()?;
+            println!("\nWrote {}", output.display());
+        }
+    }
+
+    Ok(())
+}
+
+struct Target {
+    scene: usize,
+    size: Vector2<u32>,
+    kind: TargetKind,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum TargetKind {
+    Cpu(Animation, Playback),
+    Gpu(Gpu, Renderer, Offscreen),
+}
+
+impl Target {
+    fn new(args: &TargetArgs) -> Result<Self> {
+        let mut animation = Animation::load_path(&args.path)?;
+        let scene = args.scene;
+        ensure!(scene < animation.scenes(), "Scene #{scene} does not exist");
+        animation.seed(args.seed);
+        animation.set_scene(scene, 0.0);
+
+        let size = Vector2::new(args.width, args.height);
+        let kind = if args.cpu {
+            let playback = animation.playback.clone();
+            TargetKind::Cpu(animation, playback)
+        } else {
+            let gpu = Gpu::headless(TextureFormat::Rgba8UnormSrgb, args.fallback)?;
+            let renderer = Renderer::new(&gpu, animation);
+            let offscreen = Offscreen::new(&gpu, size);
+            TargetKind::Gpu(gpu, renderer, offscreen)
+        };
+
+        Ok(Self { scene, size, kind })
+    }
+
+    fn animation(&self) -> &Animation {
+        match &self.kind {
+            TargetKind::Cpu(animation, _) => animation,
+            TargetKind::Gpu(_, renderer, _) => renderer.animation(),
+        }
+    }
+
+    fn render(&mut self, time: f32) -> RgbaImage {
+        let size = self.size;
+        match &mut self.kind {
+            TargetKind::Cpu(animation, playback) => {
+                let viewport = Viewport::full(size.cast());
+                let uniforms = Uniforms::new(animation, playback, size, &viewport, time);
+                reference::render(size, uniforms.as_ref())
+            }
+            TargetKind::Gpu(gpu, renderer, offscreen) => offscreen.render(gpu, renderer, time),
+        }
+    }
+}
+
+fn inspect(animation: &Animation) -> Result<()> {
+    let total = animation.scenes.iter().map(|x| x.duration).sum::<f32>();
+    let colormap = &animation.colormap;
+    println!(
+        "colormap: {} rows, {:.2}s cycle",
+        colormap.height(),
+        colormap.duration
+    );
+    println!("scenes: {} ({total:.2}s total)", animation.scenes());
+    println!("playlist: {:?}", animation.playlist);
+
+    let mut failed = 0;
+    for (i, scene) in animation.scenes.iter().enumerate() {
+        let size = match scene.frames.load() {
+            Ok(frames) => {
+                let size = frames.first().map(|x| x.size).unwrap_or_default();
+                format!("{}x{}", size.x, size.y)
+            }
+            Err(err) => {
+                failed += 1;
+                format!("failed to load: {err:#}")
+            }
+        };
+        println!(
+            "  #{i}: {} frames, {:.2}s, weight {}, {size}",
+            scene.frames.len(),
+            scene.duration,
+            scene.weight,
+        );
+    }
+
+    ensure!(failed == 0, "{failed} scenes failed to load");
+    Ok(())
+}
+
+fn validate(animation: &Animation) -> Result<()> {
+    ensure!(animation.scenes() > 0, "Animation has no scenes");
+    ensure!(animation.colormap.height() > 0, "Colormap is empty");
+    ensure!(
+        animation.colormap.duration > 0.0,
+        "Colormap has a non-positive duration"
+    );
+
+    animation.playlist.validate(animation.scenes())?;
+
+    for (i, scene) in animation.scenes.iter().enumerate() {
+        ensure!(!scene.frames.is_empty(), "Scene #{i} has no frames");
+        ensure!(
+            scene.duration > 0.0,
+            "Scene #{i} has a non-positive duration"
+        );
+
+        ensure!(
+            scene.byte_size() <= Limits::default().max_storage_buffer_binding_size as u64,
+            "Frames of scene #{i} are too large to fit on the GPU together"
+        );
+
+        let frames = scene.frames.load();
+        let frames = frames.with_context(|| format!("Failed to load scene #{i}"))?;
+        for (j, frame) in frames.iter().enumerate() {
+            let pixels = (frame.size.x * frame.size.y) as usize;
+            ensure!(pixels > 0, "Frame {j} of scene #{i} is empty");
+            ensure!(
+                frame.data.len() == pixels.div_ceil(32),
+                "Frame {j} of scene #{i} has {} words of data, expected {}",
+                frame.data.len(),
+                pixels.div_ceil(32)
+            );
+        }
+    }
+
+    let decoded =
+        Animation::load(&animation.encode()?).context("Failed to decode exported bundle")?;
+    ensure!(
+        decoded.scenes() == animation.scenes(),
+        "Round trip changed the scene count"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+/// Source of the animation time in seconds, so frames can be produced in real
+/// time or reproducibly.
+#[derive(Clone)]
+pub enum Clock {
+    /// Wall clock time, multiplied by `speed`.
+    RealTime {
+        start: Instant,
+        offset: f32,
+        speed: f32,
+    },
+    /// Advances by `step` seconds every tick, regardless of how long a frame
+    /// actually took.
+    FixedStep { time: f32, step: f32 },
+    /// Only changes when seeked.
+    Manual { time: f32 },
+}
+
+impl Clock {
+    pub fn real_time() -> Self {
+        Self::scaled(1.0)
+    }
+
+    pub fn scaled(speed: f32) -> Self {
+        Self::RealTime {
+            start: Instant::now(),
+            offset: 0.0,
+            speed,
+        }
+    }
+
+    pub fn fixed_step(step: f32) -> Self {
+        Self::FixedStep { time: 0.0, step }
+    }
+
+    pub fn manual(time: f32) -> Self {
+        Self::Manual { time }
+    }
+
+    /// The current time, without advancing fixed step clocks.
+    pub fn time(&self) -> f32 {
+        match self {
+            Self::RealTime {
+                start,
+                offset,
+                speed,
+            } => offset + start.elapsed().as_secs_f32() * speed,
+            Self::FixedStep { time, .. } | Self::Manual { time } => *time,
+        }
+    }
+
+    /// Returns the time for the next frame.
+    pub fn tick(&mut self) -> f32 {
+        let time = self.time();
+        if let Self::FixedStep { time, step } = self {
+            *time += *step;
+        }
+
+        time
+    }
+
+    pub fn seek(&mut self, to: f32) {
+        match self {
+            Self::RealTime { start, offset, .. } => {
+                *start = Instant::now();
+                *offset = to;
+            }
+            Self::FixedStep { time, .. } | Self::Manual { time } => *time = to,
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::real_time()
+    }
+}
@@ -1,5 +1,8 @@
 #![allow(dead_code)]
 
 pub mod animation;
+pub mod clock;
 pub mod interpolate;
+pub mod pacing;
 pub mod pipelines;
+pub mod record;
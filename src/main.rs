@@ -12,13 +12,14 @@ use wgpu::{
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalPosition,
-    event::WindowEvent,
-    event_loop::{ActiveEventLoop, EventLoop},
+    event::{StartCause, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     window::{Fullscreen, Window, WindowAttributes, WindowId},
 };
 
 use macintosh_wallpaper::{
-    animation::Animation,
+    animation::{Animation, RuntimeConfig},
+    pacing::Pacer,
     pipelines::{Gpu, Renderer},
 };
 
@@ -31,6 +32,7 @@ struct State {
     window: Arc<Window>,
     surface: Surface<'static>,
     renderer: Renderer,
+    pacer: Pacer,
 
     cursor_start: Option<PhysicalPosition<f64>>,
     preview: bool,
@@ -63,7 +65,17 @@ fn main() -> Result<()> {
 impl ApplicationHandler for Application {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let config = include_bytes!("../animation/animation.bin");
-        let animation = Animation::load(config).unwrap().runtime_from_args();
+        let animation = match Animation::load_runtime(config, RuntimeConfig::from_args()) {
+            Ok(animation) => animation,
+            Err(err) => {
+                eprintln!("Error: {err:#}");
+                // Screensavers have no console to print to
+                #[cfg(windows)]
+                message_box(format!("{err:#}"));
+                event_loop.exit();
+                return;
+            }
+        };
         let rt = &animation.runtime;
         let preview = rt.preview.is_some();
 
@@ -99,11 +111,13 @@ impl ApplicationHandler for Application {
         window.set_visible(true);
 
         let surface = self.gpu.instance.create_surface(window.clone()).unwrap();
+        let pacer = Pacer::new(rt.max_fps, rt.adaptive);
         let renderer = Renderer::new(&self.gpu, animation);
         self.state = Some(State {
             surface,
             window,
             renderer,
+            pacer,
 
             cursor_start: None,
             preview,
@@ -160,17 +174,30 @@ impl ApplicationHandler for Application {
 
                     let size = state.window.inner_size();
                     let size = Vector2::new(size.width, size.height);
-                    state.renderer.render(&self.gpu, size, &mut render_pass);
+                    let pacer = &mut state.pacer;
+                    (state.renderer).render(&self.gpu, size, pacer, &mut render_pass);
                 }
 
                 self.gpu.queue.submit([encoder.finish()]);
 
                 output.present();
-                state.window.request_redraw();
+                match state.pacer.next_frame() {
+                    Some(next) => event_loop.set_control_flow(ControlFlow::WaitUntil(next)),
+                    None => {
+                        event_loop.set_control_flow(ControlFlow::Wait);
+                        state.window.request_redraw();
+                    }
+                }
             }
             _ => {}
         }
     }
+
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let (StartCause::ResumeTimeReached { .. }, Some(state)) = (cause, &self.state) {
+            state.window.request_redraw();
+        }
+    }
 }
 
 impl Application {
@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant};
+
+use nalgebra::{Vector2, Vector4};
+
+use crate::pipelines::Uniforms;
+
+/// Slowest rate adaptive pacing drops to, so the colormap still moves
+/// smoothly.
+const MIN_FPS: f32 = 5.0;
+/// How far in screen pixels the image can move between frames before
+/// adaptive pacing speeds up.
+const MAX_MOVEMENT: f32 = 0.5;
+/// Fraction of the frame rate kept each frame while slowing down, so rates
+/// don't oscillate when things only change every few frames.
+const DECAY: f32 = 0.9;
+
+/// Decides when the next frame of a window should be drawn, so mostly static
+/// scenes don't redraw at the full refresh rate.
+pub struct Pacer {
+    max_fps: Option<f32>,
+    adaptive: bool,
+
+    /// Rate picked by adaptive pacing, infinite when frames should be drawn
+    /// as fast as the display allows.
+    fps: f32,
+    last: Option<Sample>,
+}
+
+/// What was visible in a drawn frame.
+struct Sample {
+    instant: Instant,
+    layers: Vec<LayerSample>,
+}
+
+struct LayerSample {
+    /// Corners of the image in screen pixels.
+    corners: [Vector2<f32>; 4],
+    image_offset: u32,
+    progress: f32,
+    /// Opacity, dissolve and wipe position.
+    transition: [f32; 3],
+}
+
+impl Pacer {
+    pub fn new(max_fps: Option<f32>, adaptive: bool) -> Self {
+        Self {
+            max_fps: max_fps.filter(|&x| x > 0.0),
+            adaptive,
+
+            fps: f32::INFINITY,
+            last: None,
+        }
+    }
+
+    /// Records a drawn frame, `None` if it was completely faded out.
+    pub fn update(&mut self, uniforms: Option<&Uniforms>) {
+        self.record(Sample::new(uniforms));
+    }
+
+    fn record(&mut self, sample: Sample) {
+        self.fps = match &self.last {
+            Some(last) if self.adaptive => {
+                // Slow down from the rate frames were actually drawn at, as
+                // the picked rate is infinite while drawing as fast as possible
+                let elapsed = (sample.instant - last.instant).as_secs_f32();
+                let drawn = self.fps.min(elapsed.recip());
+                let needed = sample.needed_fps(last);
+                needed.max(drawn * DECAY).max(MIN_FPS)
+            }
+            _ => f32::INFINITY,
+        };
+        self.last = Some(sample);
+    }
+
+    /// When the next frame should be drawn, or `None` to draw it as soon as
+    /// the display is ready.
+    pub fn next_frame(&self) -> Option<Instant> {
+        let fps = self.fps.min(self.max_fps.unwrap_or(f32::INFINITY));
+        let last = self.last.as_ref()?;
+        fps.is_finite()
+            .then(|| last.instant + Duration::from_secs_f32(fps.recip()))
+    }
+}
+
+impl Sample {
+    fn new(uniforms: Option<&Uniforms>) -> Self {
+        let layers = uniforms.iter().flat_map(|x| &x.layers);
+        let layers = layers.map(|layer| {
+            let pixels = &layer.pixels;
+            let window = pixels.window_size.cast::<f32>();
+            let corner = |x: f32, y: f32| {
+                let clip = pixels.view * Vector4::new(x, y, 0.0, 1.0);
+                let ndc = clip.xy() / clip.w;
+                (ndc * 0.5).add_scalar(0.5).component_mul(&window)
+            };
+
+            LayerSample {
+                corners: [
+                    corner(-1.0, -1.0),
+                    corner(1.0, -1.0),
+                    corner(1.0, 1.0),
+                    corner(-1.0, 1.0),
+                ],
+                image_offset: pixels.image_offset,
+                progress: pixels.progress,
+                transition: [pixels.opacity, pixels.dissolve, pixels.wipe.z],
+            }
+        });
+
+        Self {
+            instant: Instant::now(),
+            layers: layers.collect(),
+        }
+    }
+
+    /// Frame rate needed to keep up with the changes since `last`.
+    fn needed_fps(&self, last: &Sample) -> f32 {
+        if self.layers.len() != last.layers.len() {
+            return f32::INFINITY;
+        }
+
+        let mut movement = 0.0_f32;
+        for (layer, last) in self.layers.iter().zip(&last.layers) {
+            // Frame changes, pixels shrinking and transitions are all
+            // noticeable when dropped, so play them at the full rate.
+            if (layer.image_offset, layer.progress, layer.transition)
+                != (last.image_offset, last.progress, last.transition)
+            {
+                return f32::INFINITY;
+            }
+
+            let corners = layer.corners.iter().zip(&last.corners);
+            for (a, b) in corners {
+                movement = movement.max((a - b).norm());
+            }
+        }
+
+        let elapsed = (self.instant - last.instant).as_secs_f32();
+        if movement == 0.0 {
+            0.0
+        } else if elapsed <= 0.0 {
+            f32::INFINITY
+        } else {
+            movement / elapsed / MAX_MOVEMENT
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(instant: Instant, x: f32) -> Sample {
+        let corner = |dx: f32, dy: f32| Vector2::new(x + dx * 100.0, dy * 100.0);
+        Sample {
+            instant,
+            layers: vec![LayerSample {
+                corners: [
+                    corner(0.0, 0.0),
+                    corner(1.0, 0.0),
+                    corner(1.0, 1.0),
+                    corner(0.0, 1.0),
+                ],
+                image_offset: 0,
+                progress: 1.0,
+                transition: [1.0, 0.0, 0.0],
+            }],
+        }
+    }
+
+    /// Draws `count` frames when the pacer asks for them, or at 60 fps when
+    /// it wants them as fast as possible, moving by `speed` pixels a second.
+    fn draw(pacer: &mut Pacer, start: Instant, count: usize, speed: f32) -> Instant {
+        let mut now = start;
+        for _ in 0..count {
+            now = (pacer.next_frame()).unwrap_or(now + Duration::from_secs_f32(1.0 / 60.0));
+            let x = (now - start).as_secs_f32() * speed;
+            pacer.record(sample(now, x));
+        }
+        now
+    }
+
+    #[test]
+    fn adaptive_slows_down_when_static() {
+        let mut pacer = Pacer::new(None, true);
+        draw(&mut pacer, Instant::now(), 200, 0.0);
+        assert_eq!(pacer.fps, MIN_FPS);
+        assert!(pacer.next_frame().is_some());
+    }
+
+    #[test]
+    fn adaptive_speeds_up_on_movement() {
+        let mut pacer = Pacer::new(None, true);
+        let now = draw(&mut pacer, Instant::now(), 200, 0.0);
+        draw(&mut pacer, now, 2, 100.0);
+        assert!(pacer.fps >= 100.0 / MAX_MOVEMENT, "{}", pacer.fps);
+    }
+
+    #[test]
+    fn not_adaptive_draws_as_fast_as_possible() {
+        let mut pacer = Pacer::new(None, false);
+        draw(&mut pacer, Instant::now(), 10, 0.0);
+        assert!(pacer.next_frame().is_none());
+    }
+}
@@ -1,13 +1,19 @@
-use std::time::Instant;
+use std::sync::Arc;
 
-use nalgebra::Vector2;
+use anyhow::{Context, Result};
+use nalgebra::{Vector2, Vector3};
 use wgpu::{
-    Adapter, Buffer, BufferUsages, Device, IndexFormat, Instance, Queue, RenderPass, TextureFormat,
+    Adapter, Buffer, BufferUsages, Device, IndexFormat, Instance, Queue, RenderPass,
+    RequestAdapterOptions, TextureFormat,
     util::{BufferInitDescriptor, DeviceExt},
 };
 
 use crate::{
-    animation::Animation,
+    animation::{
+        Animation, Playback, SceneFrame, properties::Viewport, transition::TransitionKind,
+    },
+    clock::Clock,
+    pacing::Pacer,
     pipelines::{
         background::{BackgroundPipeline, BackgroundUniform},
         pixel::{PixelsPipeline, PixelsUniform},
@@ -15,15 +21,24 @@ use crate::{
 };
 
 pub mod background;
+pub mod offscreen;
 pub mod pixel;
+pub mod reference;
+
+/// Seconds it takes to fade out at the end.
+const FADE_OUT_DURATION: f32 = 3.0;
 
 pub struct Renderer {
     background: BackgroundPipeline,
-    pixels: PixelsPipeline,
+    /// One pipeline for each scene that can be visible at once.
+    pixels: [PixelsPipeline; 2],
     index: Buffer,
 
-    start: Instant,
-    animation: Animation,
+    clock: Clock,
+    animation: Arc<Animation>,
+    playback: Playback,
+    /// Part of the canvas to draw, all of it if unset.
+    viewport: Option<Viewport>,
 }
 
 pub struct Gpu {
@@ -35,8 +50,39 @@ pub struct Gpu {
     pub texture_format: TextureFormat,
 }
 
+impl Gpu {
+    /// Creates a device that isn't tied to any surface, optionally forcing a
+    /// software adapter for machines without a GPU.
+    pub fn headless(texture_format: TextureFormat, fallback: bool) -> Result<Self> {
+        let instance = Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&RequestAdapterOptions {
+            force_fallback_adapter: fallback,
+            ..Default::default()
+        }))
+        .context("No adapter found")?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&Default::default(), None))?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+
+            texture_format,
+        })
+    }
+}
+
 impl Renderer {
     pub fn new(gpu: &Gpu, animation: Animation) -> Self {
+        let playback = animation.playback.clone();
+        Self::with_playback(gpu, Arc::new(animation), playback)
+    }
+
+    /// Creates a renderer for an animation shared with other renderers, each
+    /// following their own `playback`.
+    pub fn with_playback(gpu: &Gpu, animation: Arc<Animation>, playback: Playback) -> Self {
         let index: [u16; 6] = [0, 1, 2, 2, 3, 0];
         let index = gpu.device.create_buffer_init(&BufferInitDescriptor {
             label: None,
@@ -46,54 +92,250 @@ impl Renderer {
 
         Self {
             background: BackgroundPipeline::new(gpu),
-            pixels: PixelsPipeline::new(gpu),
+            pixels: [(); 2].map(|_| PixelsPipeline::new(gpu, animation.max_scene_size())),
             index,
 
-            start: Instant::now(),
+            clock: Clock::scaled(animation.runtime.speed),
             animation,
+            playback,
+            viewport: None,
         }
     }
 
-    pub fn render(&mut self, gpu: &Gpu, size: Vector2<u32>, render_pass: &mut RenderPass) {
-        let aspect = size.x as f32 / size.y as f32;
+    pub fn with_clock(self, clock: Clock) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub fn clock(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
+    pub fn animation(&self) -> &Animation {
+        &self.animation
+    }
 
-        let time = self.start.elapsed().as_secs_f32();
-        let t = (time / 60.0) % 1.0;
+    pub fn playback(&mut self) -> &mut Playback {
+        &mut self.playback
+    }
 
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    /// Starts fading out from the current time.
+    pub fn fade_out(&mut self) {
+        let rt = &self.animation.runtime;
+        let end = self.clock.time() + rt.fade_duration + FADE_OUT_DURATION;
+        self.playback.fade_out = Some(end);
+    }
+
+    /// Whether frames are completely faded out, after which nothing is drawn.
+    pub fn faded_out(&self) -> bool {
         let rt = &self.animation.runtime;
-        let (fd, fi, fo) = (rt.fade_duration, rt.fade_in, rt.fade_out);
+        let fade_out = self.playback.fade_out.or(rt.fade_out);
+        fade_out.is_some_and(|x| self.clock.time() >= x - rt.fade_duration)
+    }
+
+    /// Color the scenes are currently drawn in, for things drawn on top.
+    pub fn foreground(&self) -> Vector3<f32> {
+        let colormap = &self.animation.colormap;
+        let t = colormap.cycle(self.clock.time(), self.playback.colormap_offset);
+        colormap.get_foreground(t)
+    }
+
+    /// Draws the frame at the current time, recording it in `pacer`.
+    pub fn render(
+        &mut self,
+        gpu: &Gpu,
+        size: Vector2<u32>,
+        pacer: &mut Pacer,
+        render_pass: &mut RenderPass,
+    ) {
+        let time = self.clock.tick();
+        self.draw(gpu, size, time, Some(pacer), render_pass);
+    }
+
+    pub fn render_at(
+        &mut self,
+        gpu: &Gpu,
+        size: Vector2<u32>,
+        time: f32,
+        render_pass: &mut RenderPass,
+    ) {
+        self.draw(gpu, size, time, None, render_pass);
+    }
+
+    fn draw(
+        &mut self,
+        gpu: &Gpu,
+        size: Vector2<u32>,
+        time: f32,
+        pacer: Option<&mut Pacer>,
+        render_pass: &mut RenderPass,
+    ) {
+        let viewport = (self.viewport).unwrap_or_else(|| Viewport::full(size.cast()));
+        let (animation, playback) = (&self.animation, &mut self.playback);
+        let uniforms = Uniforms::new(animation, playback, size, &viewport, time);
+        if let Some(pacer) = pacer {
+            pacer.update(uniforms.as_ref());
+        }
+
+        let Some(uniforms) = uniforms else {
+            return;
+        };
+
+        self.background.prepare(gpu, &uniforms.background);
+        let assignment = assign(&self.pixels, &uniforms.layers);
+        for (&i, layer) in assignment.iter().zip(&uniforms.layers) {
+            let scene = &layer.scene;
+            self.pixels[i].upload(gpu, scene.index, &scene.frames);
+            self.pixels[i].prepare(gpu, &layer.pixels);
+        }
+
+        render_pass.set_index_buffer(self.index.slice(..), IndexFormat::Uint16);
+        self.background.paint(render_pass);
+        for &i in &assignment {
+            self.pixels[i].paint(render_pass);
+        }
+    }
+}
+
+/// Everything the pipelines need to draw one frame.
+pub struct Uniforms {
+    pub background: BackgroundUniform,
+    /// Scenes to draw in order, during a transition the outgoing scene comes
+    /// first.
+    pub layers: Vec<Layer>,
+}
+
+pub struct Layer {
+    pub pixels: PixelsUniform,
+    pub scene: SceneFrame,
+}
+
+impl Uniforms {
+    /// Advances `playback` to `time` and computes the uniforms for a target
+    /// of `size` showing `viewport` of the canvas, or `None` when the frame is
+    /// completely faded out.
+    pub fn new(
+        animation: &Animation,
+        playback: &mut Playback,
+        size: Vector2<u32>,
+        viewport: &Viewport,
+        time: f32,
+    ) -> Option<Self> {
+        let (min, max) = viewport.bounds();
+        let t = animation.colormap.cycle(time, playback.colormap_offset);
+
+        let rt = &animation.runtime;
+        let (fd, fi) = (rt.fade_duration, rt.fade_in);
+        let fo = playback.fade_out.or(rt.fade_out);
 
         let fade_in = if fi { (time / fd).clamp(0.0, 1.0) } else { 1.0 };
-        let fade_out = (fo.map(|fo| ((fo - fd - time) / 3.0).clamp(0.0, 1.0))).unwrap_or(1.0);
+        let fade_out = fo.map(|fo| ((fo - fd - time) / FADE_OUT_DURATION).clamp(0.0, 1.0));
+        let fade_out = fade_out.unwrap_or(1.0);
         let fade = fade_in * fade_out;
         if fade <= 0.0 {
-            return;
+            return None;
         }
 
-        let colormap = &self.animation.colormap;
+        let colormap = &animation.colormap;
         let foreground = colormap.get_foreground(t);
 
-        let background_uniform = BackgroundUniform {
-            start: colormap.get_background_top(t) * fade,
-            end: colormap.get_background_bottom(t) * fade,
+        let (top, bottom) = (
+            colormap.get_background_top(t),
+            colormap.get_background_bottom(t),
+        );
+        let background = BackgroundUniform {
+            start: top.lerp(&bottom, min.y) * fade,
+            end: top.lerp(&bottom, max.y) * fade,
+        };
+
+        let layer = |scene: SceneFrame| Layer {
+            pixels: PixelsUniform {
+                view: scene.properties.view_projection(viewport),
+                image_size: scene.image().size,
+                window_size: size,
+                image_offset: scene.frames[..scene.frame]
+                    .iter()
+                    .map(|x| x.data.len() as u32)
+                    .sum(),
+                color: foreground * fade,
+                scale: scene.properties.scale,
+                progress: scene.properties.progress,
+                progress_angle: scene.properties.progress_angle,
+
+                opacity: 1.0,
+                dissolve: 1.0,
+                wipe: Vector3::z(),
+            },
+            scene,
         };
 
-        let (properties, image) = self.animation.scene(time);
-        let pixels_uniform = PixelsUniform {
-            view: properties.view_projection(aspect),
-            image_size: image.size,
-            window_size: size,
-            color: foreground * fade,
-            scale: properties.scale,
-            progress: properties.progress,
-            progress_angle: properties.progress_angle,
+        let frame = animation.frame_with(playback, time);
+        let mut incoming = layer(frame.scene);
+        let Some(outgoing) = frame.transition else {
+            return Some(Self {
+                background,
+                layers: vec![incoming],
+            });
         };
 
-        self.background.prepare(gpu, &background_uniform);
-        self.pixels.prepare(gpu, &pixels_uniform, &image.data);
+        let mut previous = layer(outgoing.scene);
+        let progress = outgoing.progress;
+        match outgoing.transition.kind {
+            TransitionKind::CrossFade => {
+                previous.pixels.opacity = 1.0 - progress;
+                incoming.pixels.opacity = progress;
+            }
+            TransitionKind::Dissolve => {
+                previous.pixels.dissolve = 1.0 - progress;
+                incoming.pixels.dissolve = progress;
+            }
+            TransitionKind::Wipe => {
+                // Move the line between the corners of the screen that are
+                // furthest back and forward along the wipe direction.
+                let angle = outgoing.transition.angle;
+                let dir = Vector2::new(angle.cos(), angle.sin());
+                let low = dir.x.min(0.0) + dir.y.min(0.0);
+                let high = dir.x.max(0.0) + dir.y.max(0.0);
+                let line = low + (high - low) * progress;
 
-        render_pass.set_index_buffer(self.index.slice(..), IndexFormat::Uint16);
-        self.background.paint(render_pass);
-        self.pixels.paint(render_pass);
+                // Then move it from canvas to target space
+                let wipe = dir.component_mul(&(max - min)).push(line - dir.dot(&min));
+                previous.pixels.wipe = -wipe;
+                incoming.pixels.wipe = wipe;
+            }
+        }
+
+        Some(Self {
+            background,
+            layers: vec![previous, incoming],
+        })
     }
 }
+
+/// Picks a pixel pipeline for each layer, preferring ones that already
+/// have the layer's scene uploaded so scenes aren't copied to the GPU
+/// again when a transition starts or ends.
+fn assign(pixels: &[PixelsPipeline; 2], layers: &[Layer]) -> Vec<usize> {
+    let mut free = [true; 2];
+    let mut assignment = vec![None; layers.len()];
+
+    for (slot, layer) in assignment.iter_mut().zip(layers) {
+        let loaded = (0..2).find(|&i| free[i] && pixels[i].scene() == Some(layer.scene.index));
+        if let Some(i) = loaded {
+            free[i] = false;
+            *slot = Some(i);
+        }
+    }
+
+    let assignment = assignment.into_iter().map(|slot| {
+        slot.unwrap_or_else(|| {
+            let i = (0..2).find(|&i| free[i]).unwrap();
+            free[i] = false;
+            i
+        })
+    });
+    assignment.collect()
+}
@@ -0,0 +1,133 @@
+use image::RgbaImage;
+use nalgebra::Vector2;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, COPY_BYTES_PER_ROW_ALIGNMENT, Color,
+    CommandEncoderDescriptor, Extent3d, LoadOp, Maintain, MapMode, Operations, Origin3d,
+    RenderPassColorAttachment, RenderPassDescriptor, StoreOp, TexelCopyBufferInfo,
+    TexelCopyBufferLayout, TexelCopyTextureInfo, Texture, TextureAspect, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
+};
+
+use crate::pipelines::{Gpu, Renderer};
+
+/// Renders into a texture instead of a surface and reads the result back to
+/// the CPU, so frames can be produced without a display.
+pub struct Offscreen {
+    texture: Texture,
+    buffer: Buffer,
+    size: Vector2<u32>,
+    row_bytes: u32,
+}
+
+impl Offscreen {
+    pub fn new(gpu: &Gpu, size: Vector2<u32>) -> Self {
+        let extent = Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = gpu.device.create_texture(&TextureDescriptor {
+            label: None,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: gpu.texture_format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let row_bytes = (size.x * 4).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = gpu.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: row_bytes as u64 * size.y as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            texture,
+            buffer,
+            size,
+            row_bytes,
+        }
+    }
+
+    pub fn size(&self) -> Vector2<u32> {
+        self.size
+    }
+
+    /// Renders the animation at `time` seconds and returns the frame.
+    pub fn render(&self, gpu: &Gpu, renderer: &mut Renderer, time: f32) -> RgbaImage {
+        let view = self.texture.create_view(&TextureViewDescriptor::default());
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor::default());
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            renderer.render_at(gpu, self.size, time, &mut render_pass);
+        }
+
+        encoder.copy_texture_to_buffer(
+            TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            TexelCopyBufferInfo {
+                buffer: &self.buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            self.texture.size(),
+        );
+        gpu.queue.submit([encoder.finish()]);
+
+        let slice = self.buffer.slice(..);
+        slice.map_async(MapMode::Read, |result| result.unwrap());
+        gpu.device.poll(Maintain::Wait);
+
+        let bgra = matches!(
+            gpu.texture_format,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+        );
+
+        let mut image = RgbaImage::new(self.size.x, self.size.y);
+        {
+            let data = slice.get_mapped_range();
+            for (y, row) in data.chunks(self.row_bytes as usize).enumerate() {
+                for (x, pixel) in row.chunks(4).take(self.size.x as usize).enumerate() {
+                    let mut pixel = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                    if bgra {
+                        pixel.swap(0, 2);
+                    }
+
+                    image.put_pixel(x as u32, y as u32, pixel.into());
+                }
+            }
+        }
+        self.buffer.unmap();
+
+        image
+    }
+}
@@ -3,7 +3,7 @@ use std::borrow::Cow;
 use encase::{ShaderSize, ShaderType, UniformBuffer};
 use nalgebra::{Matrix4, Vector2, Vector3};
 use wgpu::{
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent, BlendState, Buffer,
     BufferBinding, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState,
     ColorWrites, FragmentState, MultisampleState, PipelineCompilationOptions,
@@ -11,13 +11,16 @@ use wgpu::{
     ShaderModuleDescriptor, ShaderSource, ShaderStages, VertexState,
 };
 
-use crate::pipelines::Gpu;
+use crate::{animation::Image, pipelines::Gpu};
 
 pub struct PixelsPipeline {
     pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
     bind_group: BindGroup,
     uniform: Buffer,
     image: Buffer,
+    /// Index of the scene whose frames are in `image`.
+    scene: Option<usize>,
 }
 
 #[derive(ShaderType, Default)]
@@ -25,14 +28,25 @@ pub struct PixelsUniform {
     pub view: Matrix4<f32>,
     pub image_size: Vector2<u32>,
     pub window_size: Vector2<u32>,
+    /// Index of the first word of the current frame in the image buffer.
+    pub image_offset: u32,
     pub color: Vector3<f32>,
     pub scale: f32,
     pub progress: f32,
     pub progress_angle: f32,
+
+    /// Multiplier for the whole layer, used for cross-fades.
+    pub opacity: f32,
+    /// How much of a dissolve has been played, 1 shows every pixel.
+    pub dissolve: f32,
+    /// Screen space (0-1) points are kept if `dot(point, wipe.xy) <= wipe.z`.
+    pub wipe: Vector3<f32>,
 }
 
 impl PixelsPipeline {
-    pub fn new(gpu: &Gpu) -> Self {
+    /// Creates the pipeline with room for scenes of up to `image_size` bytes,
+    /// larger scenes will reallocate the buffer when uploaded.
+    pub fn new(gpu: &Gpu, image_size: u64) -> Self {
         let device = &gpu.device;
 
         let shader = device.create_shader_module(ShaderModuleDescriptor {
@@ -50,12 +64,7 @@ impl PixelsPipeline {
             mapped_at_creation: false,
         });
 
-        let image = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: 512 * 512,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let image = create_image_buffer(gpu, image_size);
 
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: None,
@@ -83,28 +92,7 @@ impl PixelsPipeline {
             ],
         });
 
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &uniform,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &image,
-                        offset: 0,
-                        size: None,
-                    }),
-                },
-            ],
-        });
+        let bind_group = create_bind_group(gpu, &bind_group_layout, &uniform, &image);
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
@@ -143,19 +131,47 @@ impl PixelsPipeline {
 
         Self {
             pipeline,
+            bind_group_layout,
             bind_group,
             uniform,
             image,
+            scene: None,
         }
     }
 
-    pub fn prepare(&mut self, gpu: &Gpu, data: &PixelsUniform, image: &[u32]) {
+    pub fn scene(&self) -> Option<usize> {
+        self.scene
+    }
+
+    /// Copies every frame of `scene` into the image buffer back to back,
+    /// unless they are already there.
+    pub fn upload(&mut self, gpu: &Gpu, scene: usize, frames: &[Image]) {
+        if self.scene == Some(scene) {
+            return;
+        }
+
+        let image_size = frames.iter().map(|x| x.byte_size()).sum::<u64>();
+        if image_size > self.image.size() {
+            self.image = create_image_buffer(gpu, image_size);
+            self.bind_group =
+                create_bind_group(gpu, &self.bind_group_layout, &self.uniform, &self.image);
+        }
+
+        let mut offset = 0;
+        for frame in frames {
+            let data = bytemuck::cast_slice(&frame.data);
+            gpu.queue.write_buffer(&self.image, offset, data);
+            offset += data.len() as u64;
+        }
+
+        self.scene = Some(scene);
+    }
+
+    pub fn prepare(&mut self, gpu: &Gpu, data: &PixelsUniform) {
         let mut buffer = UniformBuffer::new(Vec::new());
         buffer.write(data).unwrap();
         gpu.queue
             .write_buffer(&self.uniform, 0, &buffer.into_inner());
-        gpu.queue
-            .write_buffer(&self.image, 0, bytemuck::cast_slice(image));
     }
 
     pub fn paint(&mut self, render_pass: &mut RenderPass) {
@@ -164,3 +180,43 @@ impl PixelsPipeline {
         render_pass.draw_indexed(0..6, 0, 0..1);
     }
 }
+
+fn create_image_buffer(gpu: &Gpu, size: u64) -> Buffer {
+    gpu.device.create_buffer(&BufferDescriptor {
+        label: None,
+        // Bindings can't be empty and must be a multiple of four bytes
+        size: size.max(4).next_multiple_of(4),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_group(
+    gpu: &Gpu,
+    layout: &BindGroupLayout,
+    uniform: &Buffer,
+    image: &Buffer,
+) -> BindGroup {
+    gpu.device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: uniform,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: image,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+        ],
+    })
+}
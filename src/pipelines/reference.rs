@@ -0,0 +1,229 @@
+//! CPU mirror of `shaders/background.wgsl` and `shaders/pixels.wgsl`. Given
+//! the same uniforms it produces the same image as the GPU pipelines, off by
+//! a step or two per channel from rounding, so frames can be checked without
+//! a GPU.
+
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix3, Vector2, Vector3};
+
+use crate::pipelines::{Uniforms, background::BackgroundUniform, pixel::PixelsUniform};
+
+/// Rasterizes a frame into an sRGB image, like rendering to a
+/// `Rgba8UnormSrgb` target cleared to black.
+pub fn render(size: Vector2<u32>, uniforms: Option<&Uniforms>) -> RgbaImage {
+    let Some(uniforms) = uniforms else {
+        return RgbaImage::from_pixel(size.x, size.y, Rgba([0, 0, 0, 255]));
+    };
+
+    let layers = (uniforms.layers.iter())
+        .map(|x| Pixels::new(&x.pixels, &x.scene.image().data))
+        .collect::<Vec<_>>();
+    RgbaImage::from_fn(size.x, size.y, |x, y| {
+        let ndc = |x: f32, y: f32| {
+            Vector2::new(
+                (x + 0.5) / size.x as f32 * 2.0 - 1.0,
+                1.0 - (y + 0.5) / size.y as f32 * 2.0,
+            )
+        };
+
+        let (x, y) = (x as f32, y as f32);
+        let mut color = background(&uniforms.background, ndc(x, y));
+
+        for pixels in &layers {
+            let Some(uv) = pixels.uv(ndc(x, y)) else {
+                continue;
+            };
+
+            // GPUs take derivatives across 2x2 quads, the difference
+            // between the two pixels of the row or column this one is in
+            let dist = |x: f32, y: f32| pixels.distance(pixels.unclipped_uv(ndc(x, y)));
+            let (left, top) = (x - x % 2.0, y - y % 2.0);
+            let cutoff_width = (dist(left + 1.0, y) - dist(left, y)).abs()
+                + (dist(x, top + 1.0) - dist(x, top)).abs();
+            let screen = Vector2::new((x + 0.5) / size.x as f32, (y + 0.5) / size.y as f32);
+            let alpha = pixels.evaluate(uv, cutoff_width) * pixels.mask(screen);
+            color = pixels.uniform.color * alpha + color * (1.0 - alpha);
+        }
+
+        let color = color.map(|x| (linear_to_srgb(x) * 255.0).round() as u8);
+        Rgba([color.x, color.y, color.z, 255])
+    })
+}
+
+/// Mirrors `frag` in `background.wgsl` for a point in normalized device
+/// coordinates.
+pub fn background(uniform: &BackgroundUniform, ndc: Vector2<f32>) -> Vector3<f32> {
+    let uv_y = (ndc.y + 1.0) / 2.0;
+    uniform.start.lerp(&uniform.end, 1.0 - uv_y)
+}
+
+/// Mirrors the vertex and fragment stages of `pixels.wgsl`.
+pub struct Pixels<'a> {
+    uniform: &'a PixelsUniform,
+    image: &'a [u32],
+    homography: Matrix3<f32>,
+    inverse: Matrix3<f32>,
+    depth: Vector3<f32>,
+}
+
+impl<'a> Pixels<'a> {
+    pub fn new(uniform: &'a PixelsUniform, image: &'a [u32]) -> Self {
+        // The quad lies in the z = 0 plane so only the x, y and w columns of
+        // the view matrix matter, leaving a 2D homography to invert.
+        let view = &uniform.view;
+        let homography = Matrix3::new(
+            view[(0, 0)],
+            view[(0, 1)],
+            view[(0, 3)],
+            view[(1, 0)],
+            view[(1, 1)],
+            view[(1, 3)],
+            view[(3, 0)],
+            view[(3, 1)],
+            view[(3, 3)],
+        );
+        let depth = Vector3::new(view[(2, 0)], view[(2, 1)], view[(2, 3)]);
+
+        Self {
+            uniform,
+            image,
+            homography,
+            inverse: homography.try_inverse().unwrap_or_else(Matrix3::zeros),
+            depth,
+        }
+    }
+
+    /// Finds the quad uv under a point, or `None` if the quad doesn't cover it.
+    pub fn uv(&self, ndc: Vector2<f32>) -> Option<Vector2<f32>> {
+        let uv = self.unclipped_uv(ndc);
+        if !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y) {
+            return None;
+        }
+
+        let pos = (uv * 2.0 - Vector2::repeat(1.0)).push(1.0);
+        let z = self.depth.dot(&pos) / (self.homography * pos).z;
+        (0.0..=1.0).contains(&z).then_some(uv)
+    }
+
+    fn unclipped_uv(&self, ndc: Vector2<f32>) -> Vector2<f32> {
+        let quad = self.inverse * ndc.push(1.0);
+        let quad = quad.xy() / quad.z;
+        (quad + Vector2::repeat(1.0)) / 2.0
+    }
+
+    /// Mirrors `evaluate`, with `cutoff_width` standing in for `fwidth(dist)`.
+    pub fn evaluate(&self, uv: Vector2<f32>, cutoff_width: f32) -> f32 {
+        let rounded = self.position(uv).map(|x| x.round_ties_even() as u32);
+        let edge =
+            self.distance(uv) - 0.45 * self.progress(uv).clamp(0.0, 1.0) * self.dissolve(rounded);
+        let pixel = self.pixel(rounded);
+        let value = (!pixel) as u32 as f32 + smoothstep(-cutoff_width, cutoff_width, edge);
+
+        (1.0 - value).clamp(0.0, 1.0)
+    }
+
+    /// Mirrors `dist` in `evaluate`, the distance from `uv` to the center of
+    /// the nearest image pixel.
+    pub fn distance(&self, uv: Vector2<f32>) -> f32 {
+        let pos = self.position(uv);
+        chebyshev_distance(pos - pos.map(f32::round_ties_even))
+    }
+
+    /// Mirrors the opacity and wipe applied in `frag`, for a screen space
+    /// point from 0 to 1.
+    pub fn mask(&self, screen: Vector2<f32>) -> f32 {
+        let wipe = &self.uniform.wipe;
+        let keep = screen.dot(&wipe.xy()) <= wipe.z;
+        self.uniform.opacity * keep as u32 as f32
+    }
+
+    /// Mirrors `dissolve`.
+    pub fn dissolve(&self, pos: Vector2<u32>) -> f32 {
+        let width = 0.25;
+        ((self.uniform.dissolve * (1.0 + width) - hash(pos)) / width).clamp(0.0, 1.0)
+    }
+
+    fn position(&self, uv: Vector2<f32>) -> Vector2<f32> {
+        uv.component_mul(&self.uniform.image_size.cast()) - Vector2::repeat(0.5)
+    }
+
+    /// Mirrors `pixel`, treating reads past the end of the image as zero.
+    /// `image` only holds the current frame, so `image_offset` is ignored.
+    pub fn pixel(&self, pos: Vector2<u32>) -> bool {
+        let idx = (pos.y * self.uniform.image_size.x + pos.x) as usize;
+        let word = self.image.get(idx / 32).copied().unwrap_or_default();
+        word & (1 << (idx % 32)) == 0
+    }
+
+    pub fn progress(&self, uv: Vector2<f32>) -> f32 {
+        let angle = self.uniform.progress_angle;
+        (uv.x * angle.cos() + uv.y * angle.sin()) * 20.0 + self.uniform.progress
+    }
+}
+
+fn hash(pos: Vector2<u32>) -> f32 {
+    let mut x = (pos.x.wrapping_mul(1973)).wrapping_add(pos.y.wrapping_mul(9277));
+    x ^= x << 13;
+    let y = x.wrapping_mul(x).wrapping_mul(15731).wrapping_add(789221);
+    x = x.wrapping_mul(y).wrapping_add(1376312589);
+    (x & 0x7fffffff) as f32 / 0x7fffffff as f32
+}
+
+fn chebyshev_distance(vec: Vector2<f32>) -> f32 {
+    vec.x.abs().max(vec.y.abs())
+}
+
+fn smoothstep(low: f32, high: f32, x: f32) -> f32 {
+    if low == high {
+        return (x >= high) as u32 as f32;
+    }
+
+    let t = ((x - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        animation::{Animation, properties::Viewport},
+        pipelines::Uniforms,
+    };
+
+    /// Built from `tests/fixtures/reference/config.toml`.
+    const BUNDLE: &[u8] = include_bytes!("../../tests/fixtures/reference.bin");
+    /// The same frame halfway through a dissolve drawn by the GPU pipelines,
+    /// from `macintosh-anim render tests/fixtures/reference.bin --scene 0 -t 7
+    /// --fallback --width 192 --height 128`.
+    const GOLDEN: &[u8] = include_bytes!("../../tests/fixtures/reference.png");
+
+    #[test]
+    fn matches_gpu() {
+        let mut animation = Animation::load(BUNDLE).unwrap();
+        animation.seed(0);
+        animation.set_scene(0, 0.0);
+
+        let size = Vector2::new(192, 128);
+        let viewport = Viewport::full(size.cast());
+        let mut playback = animation.playback.clone();
+        let uniforms = Uniforms::new(&animation, &mut playback, size, &viewport, 7.0);
+        let image = render(size, uniforms.as_ref());
+        let golden = image::load_from_memory(GOLDEN).unwrap().to_rgba8();
+
+        // Blending rounds differently by up to two steps, and the odd pixel
+        // right on the border between two image pixels can round either way
+        let differ = (image.pixels().zip(golden.pixels()))
+            .filter(|(a, b)| (0..3).any(|i| a[i].abs_diff(b[i]) > 2))
+            .count();
+        assert!(differ <= image.len() / 4 / 1000, "{differ} pixels differ");
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use image::{
+    Delay, Frame, RgbaImage,
+    codecs::gif::{GifEncoder, Repeat},
+};
+use nalgebra::Vector2;
+
+/// Encodes a sequence of rendered frames into a single file, picking the
+/// format from the file extension.
+pub struct Recorder {
+    inner: Encoder,
+    size: Vector2<u32>,
+    fps: u32,
+}
+
+enum Encoder {
+    Gif(GifEncoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+    /// Uncompressed 4:4:4 YUV that ffmpeg and most players can read.
+    Y4m(BufWriter<File>),
+}
+
+impl Recorder {
+    /// Creates a recorder for `frames` frames of `size` pixels. The frame
+    /// count is needed up front by the APNG header.
+    pub fn new(path: impl AsRef<Path>, size: Vector2<u32>, fps: u32, frames: u32) -> Result<Self> {
+        let path = path.as_ref();
+        let rate = u16::try_from(fps).ok().filter(|&x| x > 0);
+        let rate = rate.context("Frame rate must be between 1 and 65535")?;
+        let writer = || File::create(path).map(BufWriter::new);
+        let extension = path
+            .extension()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default();
+
+        let inner = match extension.to_ascii_lowercase().as_str() {
+            "gif" => {
+                let mut encoder = GifEncoder::new_with_speed(writer()?, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                Encoder::Gif(encoder)
+            }
+            "png" | "apng" => {
+                let mut encoder = png::Encoder::new(writer()?, size.x, size.y);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frames, 0)?;
+                encoder.set_frame_delay(1, rate)?;
+                Encoder::Apng(encoder.write_header()?)
+            }
+            "y4m" => {
+                let mut writer = writer()?;
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{} H{} F{fps}:1 Ip A1:1 C444",
+                    size.x, size.y
+                )?;
+                Encoder::Y4m(writer)
+            }
+            _ => bail!("Unsupported output format `{extension}`, use gif, png, apng or y4m"),
+        };
+
+        Ok(Self { inner, size, fps })
+    }
+
+    pub fn push(&mut self, image: RgbaImage) -> Result<()> {
+        debug_assert_eq!(image.dimensions(), (self.size.x, self.size.y));
+
+        match &mut self.inner {
+            Encoder::Gif(encoder) => {
+                let delay = Delay::from_numer_denom_ms(1000, self.fps);
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+            }
+            Encoder::Apng(writer) => writer.write_image_data(&image)?,
+            Encoder::Y4m(writer) => {
+                writer.write_all(b"FRAME\n")?;
+
+                let pixels = image.pixels().map(|x| x.0.map(|x| x as f32 / 255.0));
+                let (mut y, mut u, mut v) = (Vec::new(), Vec::new(), Vec::new());
+                for [r, g, b, _] in pixels {
+                    // BT.601 limited range, which is what ffmpeg assumes for y4m
+                    y.push(16.0 + 65.481 * r + 128.553 * g + 24.966 * b);
+                    u.push(128.0 - 37.797 * r - 74.203 * g + 112.0 * b);
+                    v.push(128.0 + 112.0 * r - 93.786 * g - 18.214 * b);
+                }
+
+                for plane in [y, u, v] {
+                    let plane = plane.into_iter().map(|x| x.round() as u8);
+                    writer.write_all(&plane.collect::<Vec<_>>())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self.inner {
+            Encoder::Gif(encoder) => drop(encoder),
+            Encoder::Apng(writer) => writer.finish()?,
+            Encoder::Y4m(mut writer) => writer.flush()?,
+        }
+
+        Ok(())
+    }
+}
@@ -1,19 +1,46 @@
-use nalgebra::Vector2;
 use smithay_client_toolkit::{
     compositor::CompositorHandler,
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_seat,
+    delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
+    delegate_registry, delegate_seat, delegate_session_lock,
     output::{OutputHandler, OutputState},
-    reexports::client::{
-        Connection, QueueHandle,
-        protocol::{
-            wl_output::{Transform, WlOutput},
-            wl_seat::WlSeat,
-            wl_surface::WlSurface,
+    reexports::{
+        client::{
+            Connection, Dispatch, QueueHandle,
+            protocol::{
+                wl_keyboard::WlKeyboard,
+                wl_output::{Transform, WlOutput},
+                wl_pointer::WlPointer,
+                wl_seat::WlSeat,
+                wl_surface::WlSurface,
+            },
+        },
+        protocols::{
+            ext::idle_notify::v1::client::{
+                ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+                ext_idle_notifier_v1::{self, ExtIdleNotifierV1},
+            },
+            wp::{
+                fractional_scale::v1::client::{
+                    wp_fractional_scale_manager_v1::{self, WpFractionalScaleManagerV1},
+                    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+                },
+                viewporter::client::{
+                    wp_viewport::{self, WpViewport},
+                    wp_viewporter::{self, WpViewporter},
+                },
+            },
         },
     },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
-    seat::{Capability, SeatHandler, SeatState},
+    seat::{
+        Capability, SeatHandler, SeatState,
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
+        pointer::{PointerEvent, PointerEventKind, PointerHandler},
+    },
+    session_lock::{
+        SessionLock, SessionLockHandler, SessionLockSurface, SessionLockSurfaceConfigure,
+    },
     shell::{
         WaylandSurface,
         wlr_layer::{LayerShellHandler, LayerSurface, LayerSurfaceConfigure},
@@ -23,8 +50,11 @@ use smithay_client_toolkit::{
 use crate::App;
 
 impl LayerShellHandler for App {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _window: &LayerSurface) {
-        self.exit = true;
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        // Exits once the last layer is gone, unless waiting to be shown again
+        if let Some(output) = self.layer_for_surface(layer.wl_surface()) {
+            self.hide(output);
+        }
     }
 
     fn configure(
@@ -35,14 +65,27 @@ impl LayerShellHandler for App {
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        let Some(output) = self.outputs.iter().position(|x| &x.layer == layer) else {
-            return;
-        };
+        self.resize(qh, layer.wl_surface(), configure.new_size);
+    }
+}
 
-        let scale = self.outputs[output].scale_factor;
-        let size = Vector2::new(configure.new_size.0, configure.new_size.1) * scale;
-        self.configure(qh, output, size);
-        self.render(output);
+impl SessionLockHandler for App {
+    fn locked(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _lock: SessionLock) {}
+
+    fn finished(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _lock: SessionLock) {
+        eprintln!("Failed to lock the session, is another locker running?");
+        self.exit = true;
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: SessionLockSurface,
+        configure: SessionLockSurfaceConfigure,
+        _serial: u32,
+    ) {
+        self.resize(qh, surface.wl_surface(), configure.new_size);
     }
 }
 
@@ -50,10 +93,16 @@ impl CompositorHandler for App {
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &WlSurface,
-        _new_factor: i32,
+        qh: &QueueHandle<Self>,
+        surface: &WlSurface,
+        new_factor: i32,
     ) {
+        let Some(output) = self.layer_for_surface(surface) else {
+            return;
+        };
+
+        self.outputs[output].scale_factor = new_factor.max(1) as u32;
+        self.rescale(qh, output);
     }
 
     fn transform_changed(
@@ -63,6 +112,8 @@ impl CompositorHandler for App {
         _surface: &WlSurface,
         _new_transform: Transform,
     ) {
+        // Nothing to do, the compositor rotates buffers for the output and
+        // configures surfaces with their size after rotation
     }
 
     fn frame(
@@ -77,17 +128,7 @@ impl CompositorHandler for App {
         };
 
         if !self.outputs[layer].needs_config {
-            self.render(layer);
-
-            let output = &mut self.outputs[layer];
-            output.layer.wl_surface().damage_buffer(
-                0,
-                0,
-                output.size.x as i32,
-                output.size.y as i32,
-            );
-            output.layer.wl_surface().frame(qh, surface.clone());
-            surface.commit();
+            self.draw(qh, layer);
         }
     }
 
@@ -115,9 +156,22 @@ impl OutputHandler for App {
         &mut self.output_state
     }
 
-    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
-    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
-    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    fn new_output(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, output: WlOutput) {
+        if !self.active || self.dismissed {
+            return;
+        }
+
+        if let Err(err) = self.add_output(qh, output) {
+            eprintln!("Failed to create surface for new output: {err}");
+        }
+    }
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+        self.layout();
+    }
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        self.remove_output(&output);
     }
 }
 
@@ -131,10 +185,36 @@ impl SeatHandler for App {
     fn new_capability(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _seat: WlSeat,
-        _capability: Capability,
+        qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
     ) {
+        if let Some(notifier) = &self.idle_notifier
+            && self.idle_notification.is_none()
+        {
+            let timeout = self.animation.runtime.idle.unwrap_or_default();
+            let notification = notifier.get_idle_notification(timeout * 1000, &seat, qh, ());
+            self.idle_notification = Some(notification);
+        }
+
+        // Input is only needed for the password when locked, or to dismiss
+        // the screensaver
+        let listen = self.lock.is_some() || self.dismiss_on_input();
+        match capability {
+            Capability::Keyboard if listen && self.keyboard.is_none() => {
+                match self.seat_state.get_keyboard(qh, &seat, None) {
+                    Ok(keyboard) => self.keyboard = Some(keyboard),
+                    Err(err) => eprintln!("Failed to get keyboard: {err}"),
+                }
+            }
+            Capability::Pointer if self.dismiss_on_input() && self.pointer.is_none() => {
+                match self.seat_state.get_pointer(qh, &seat) {
+                    Ok(pointer) => self.pointer = Some(pointer),
+                    Err(err) => eprintln!("Failed to get pointer: {err}"),
+                }
+            }
+            _ => {}
+        }
     }
 
     fn remove_capability(
@@ -142,13 +222,103 @@ impl SeatHandler for App {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _seat: WlSeat,
-        _capability: Capability,
+        capability: Capability,
     ) {
+        if capability == Capability::Keyboard
+            && let Some(keyboard) = self.keyboard.take()
+        {
+            keyboard.release();
+        }
+
+        if capability == Capability::Pointer
+            && let Some(pointer) = self.pointer.take()
+        {
+            pointer.release();
+        }
     }
 
     fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
 }
 
+impl KeyboardHandler for App {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+        _raw: &[u32],
+        _keysyms: &[Keysym],
+    ) {
+    }
+
+    fn leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+    ) {
+    }
+
+    fn press_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        event: KeyEvent,
+    ) {
+        match &mut self.lock {
+            Some(lock) => lock.key(&event),
+            None => self.dismiss(),
+        }
+    }
+
+    fn release_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _event: KeyEvent,
+    ) {
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _modifiers: Modifiers,
+        _layout: u32,
+    ) {
+    }
+}
+
+impl PointerHandler for App {
+    fn pointer_frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        pointer: &WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            match event.kind {
+                // Entering happens as soon as the surface appears under the
+                // cursor, so just hide it
+                PointerEventKind::Enter { serial } => pointer.set_cursor(serial, None, 0, 0),
+                PointerEventKind::Leave { .. } => {}
+                _ => self.dismiss(),
+            }
+        }
+    }
+}
+
 impl ProvidesRegistryState for App {
     fn registry(&mut self) -> &mut RegistryState {
         &mut self.registry_state
@@ -160,5 +330,95 @@ impl ProvidesRegistryState for App {
 delegate_compositor!(App);
 delegate_output!(App);
 delegate_seat!(App);
+delegate_keyboard!(App);
+delegate_pointer!(App);
+delegate_session_lock!(App);
 delegate_layer!(App);
 delegate_registry!(App);
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        let Some(output) = app.layer_for_surface(surface) else {
+            return;
+        };
+
+        // Sent as a fraction with a denominator of 120
+        app.outputs[output].preferred_scale = Some(scale as f64 / 120.0);
+        app.rescale(qh, output);
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for App {
+    fn event(
+        _app: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: wp_fractional_scale_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for App {
+    fn event(
+        _app: &mut Self,
+        _proxy: &WpViewporter,
+        _event: wp_viewporter::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpViewport, ()> for App {
+    fn event(
+        _app: &mut Self,
+        _proxy: &WpViewport,
+        _event: wp_viewport::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, ()> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_idle_notification_v1::Event::Idled => app.show(qh),
+            ext_idle_notification_v1::Event::Resumed => app.dismiss(),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtIdleNotifierV1, ()> for App {
+    fn event(
+        _app: &mut Self,
+        _proxy: &ExtIdleNotifierV1,
+        _event: ext_idle_notifier_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
@@ -0,0 +1,108 @@
+use std::{ptr, thread};
+
+use smithay_client_toolkit::{
+    reexports::calloop::channel::Sender,
+    seat::keyboard::{KeyEvent, Keysym},
+    session_lock::SessionLock,
+};
+
+#[cfg(feature = "lock")]
+use crate::pam;
+
+/// Longest password in bytes that can be typed, the buffer is allocated up
+/// front and never grows.
+const PASSWORD_CAPACITY: usize = 256;
+
+/// Session lock held in `--lock` mode, along with the password typed so far.
+pub struct Lock {
+    pub session: SessionLock,
+    pub password: String,
+    /// Set while PAM checks the password, input is ignored until it's done.
+    pub checking: bool,
+    results: Sender<bool>,
+}
+
+impl Lock {
+    pub fn new(session: SessionLock, results: Sender<bool>) -> Self {
+        Self {
+            session,
+            password: String::with_capacity(PASSWORD_CAPACITY),
+            checking: false,
+            results,
+        }
+    }
+
+    pub fn key(&mut self, event: &KeyEvent) {
+        if self.checking {
+            return;
+        }
+
+        match event.keysym {
+            Keysym::Return | Keysym::KP_Enter => self.submit(),
+            Keysym::BackSpace => drop(self.password.pop()),
+            Keysym::Escape => self.clear(),
+            _ => {
+                let text = event.utf8.iter().flat_map(|x| x.chars());
+                for char in text.filter(|x| !x.is_control()) {
+                    // Growing the password would leave copies of it behind
+                    if self.password.len() + char.len_utf8() > PASSWORD_CAPACITY {
+                        break;
+                    }
+                    self.password.push(char);
+                }
+            }
+        }
+    }
+
+    /// Checks the password on another thread, as PAM can take seconds to
+    /// reject one. The result is sent back to the event loop.
+    fn submit(&mut self) {
+        self.checking = true;
+        let (mut password, results) = (self.password.clone(), self.results.clone());
+        thread::spawn(move || {
+            #[cfg(feature = "lock")]
+            let result = pam::authenticate(&password);
+            // Lock mode refuses to start without the feature
+            #[cfg(not(feature = "lock"))]
+            let result = Err::<(), _>(anyhow::anyhow!("Built without lock mode"));
+            wipe(&mut password);
+            if let Err(err) = &result {
+                eprintln!("{err}");
+            }
+            let _ = results.send(result.is_ok());
+        });
+    }
+
+    /// Unlocks the session if the password was right, returning whether it
+    /// was.
+    pub fn authenticated(&mut self, success: bool) -> bool {
+        self.checking = false;
+        self.clear();
+        if success {
+            self.session.unlock();
+        }
+        success
+    }
+
+    fn clear(&mut self) {
+        wipe(&mut self.password);
+    }
+}
+
+/// Zeros a typed password before emptying it, along with anything deleted
+/// from the end of it, so it doesn't linger in freed memory.
+fn wipe(password: &mut String) {
+    // Safety: the string is left empty
+    let bytes = unsafe { password.as_mut_vec() };
+    bytes.resize(bytes.capacity(), 0);
+    zero(bytes);
+    bytes.clear();
+}
+
+/// Overwrites a password with zeros, in a way the compiler can't skip because
+/// the buffer is about to be freed.
+pub fn zero(bytes: &mut [u8]) {
+    for byte in bytes {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
@@ -3,22 +3,47 @@
 // ↓ Needed due to a rust-analyzer bug
 #![allow(dead_code)]
 
-use std::{mem, ptr::NonNull};
+use std::{mem, ptr::NonNull, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{Result, ensure};
 use nalgebra::Vector2;
 use smithay_client_toolkit::{
     compositor::CompositorState,
     output::OutputState,
-    reexports::client::{
-        Connection, Proxy, QueueHandle, globals::registry_queue_init,
-        protocol::wl_surface::WlSurface,
+    reexports::{
+        calloop::{
+            EventLoop, LoopHandle, channel,
+            timer::{TimeoutAction, Timer},
+        },
+        calloop_wayland_source::WaylandSource,
+        client::{
+            Connection, Proxy, QueueHandle,
+            globals::registry_queue_init,
+            protocol::{
+                wl_keyboard::WlKeyboard, wl_output::WlOutput, wl_pointer::WlPointer,
+                wl_surface::WlSurface,
+            },
+        },
+        protocols::{
+            ext::idle_notify::v1::client::{
+                ext_idle_notification_v1::ExtIdleNotificationV1,
+                ext_idle_notifier_v1::ExtIdleNotifierV1,
+            },
+            wp::{
+                fractional_scale::v1::client::{
+                    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+                    wp_fractional_scale_v1::WpFractionalScaleV1,
+                },
+                viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+            },
+        },
     },
     registry::RegistryState,
     seat::SeatState,
+    session_lock::{SessionLockState, SessionLockSurface},
     shell::{
         WaylandSurface,
-        wlr_layer::{Layer, LayerShell, LayerSurface},
+        wlr_layer::{Anchor, KeyboardInteractivity, Layer, LayerShell, LayerSurface},
     },
 };
 use wgpu::{
@@ -28,12 +53,21 @@ use wgpu::{
     rwh::{RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle},
 };
 
+use lock::Lock;
+use prompt::Prompt;
+
 use macintosh_wallpaper::{
-    animation::Animation,
+    animation::{Animation, Playback, RuntimeConfig, properties::Viewport},
+    clock::Clock,
+    pacing::Pacer,
     pipelines::{Gpu, Renderer},
 };
 
 mod impls;
+mod lock;
+#[cfg(feature = "lock")]
+mod pam;
+mod prompt;
 
 const NAMESPACE: &str = "com.connorcode.macintosh-wallpaper";
 
@@ -41,26 +75,73 @@ struct App {
     output_state: OutputState,
     seat_state: SeatState,
     registry_state: RegistryState,
-
-    renderer: Renderer,
+    compositor_state: CompositorState,
+    layer_shell: LayerShell,
+    /// Only used when both are supported, as fractional scales need a
+    /// viewport to map the buffer back to the surface size.
+    fractional_scale: Option<(WpFractionalScaleManagerV1, WpViewporter)>,
+    loop_handle: LoopHandle<'static, App>,
+    display: RawDisplayHandle,
+
+    animation: Arc<Animation>,
+    /// Shared by every output so colormaps and mirrored scenes line up, even
+    /// on outputs added later.
+    clock: Clock,
+    playback: Playback,
     gpu: Gpu,
     exit: bool,
 
+    /// Set in `--lock` mode, where outputs get lock surfaces instead of
+    /// layer surfaces.
+    lock: Option<Lock>,
+    keyboard: Option<WlKeyboard>,
+    pointer: Option<WlPointer>,
+    /// Bound with `--idle`, the notification is created once a seat is known.
+    idle_notifier: Option<ExtIdleNotifierV1>,
+    idle_notification: Option<ExtIdleNotificationV1>,
+    /// Whether outputs should be covered, only false while idle mode is
+    /// waiting for the seat to go idle.
+    active: bool,
+    /// Set on input, outputs are removed once they have faded out.
+    dismissed: bool,
     outputs: Vec<Output>,
+    /// Outputs ever added, so independent outputs never reuse a playback
+    /// when others are unplugged.
+    added_outputs: usize,
 }
 
 struct Output {
+    // Dropped before `shell`, as the surface refers to it
     surface: Surface<'static>,
-    layer: LayerSurface,
+    shell: Shell,
+    output: WlOutput,
+    fractional_scale: Option<(WpFractionalScaleV1, WpViewport)>,
+
     scale_factor: u32,
+    /// Preferred scale from `wp_fractional_scale_v1`, used in place of
+    /// `scale_factor` once known.
+    preferred_scale: Option<f64>,
     needs_config: bool,
+    /// Size of the surface in logical pixels, as configured by the compositor.
+    logical_size: Vector2<u32>,
+    /// Size of the buffer in physical pixels.
     size: Vector2<u32>,
+    renderer: Renderer,
+    pacer: Pacer,
+    prompt: Option<Prompt>,
+}
+
+/// Role given to the surface of an output.
+enum Shell {
+    Layer(LayerSurface),
+    Lock(SessionLockSurface),
 }
 
 fn main() -> Result<()> {
     let conn = Connection::connect_to_env()?;
     let (globals, mut event_queue) = registry_queue_init(&conn)?;
     let qh = event_queue.handle();
+    let mut event_loop = EventLoop::<App>::try_new()?;
 
     let instance = Instance::new(&InstanceDescriptor::default());
     let adapter =
@@ -69,7 +150,7 @@ fn main() -> Result<()> {
         pollster::block_on(adapter.request_device(&DeviceDescriptor::default(), None))?;
 
     let config = include_bytes!("../../animation/animation.bin");
-    let animation = Animation::load(config)?.runtime_from_args();
+    let animation = Animation::load_runtime(config, RuntimeConfig::from_args())?;
 
     let gpu = Gpu {
         instance,
@@ -79,69 +160,299 @@ fn main() -> Result<()> {
 
         texture_format: TextureFormat::Bgra8UnormSrgb,
     };
-    let renderer = Renderer::new(&gpu, animation);
 
+    // Password checks run on another thread and report back through a channel
+    let lock = if animation.runtime.lock {
+        ensure!(
+            cfg!(feature = "lock"),
+            "Lock mode needs the wayland crate to be built with `--features lock`"
+        );
+        #[cfg(feature = "lock")]
+        pam::check_service()?;
+
+        let (sender, receiver) = channel::channel();
+        event_loop
+            .handle()
+            .insert_source(receiver, |event, _, app| {
+                if let channel::Event::Msg(success) = event {
+                    app.authenticated(success);
+                }
+            })
+            .map_err(|x| x.error)?;
+
+        let session = SessionLockState::new(&globals, &qh).lock(&qh)?;
+        Some(Lock::new(session, sender))
+    } else {
+        None
+    };
+
+    let display = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
+        NonNull::new(conn.backend().display_ptr() as _).unwrap(),
+    ));
+
+    let idle_notifier = match animation.runtime.idle {
+        Some(_) => Some(globals.bind(&qh, 1..=1, ())?),
+        None => None,
+    };
+
+    // Layer or lock surfaces are created as outputs are announced, including
+    // the ones present at startup.
     let mut app = App {
         output_state: OutputState::new(&globals, &qh),
         seat_state: SeatState::new(&globals, &qh),
         registry_state: RegistryState::new(&globals),
-
-        renderer,
+        compositor_state: CompositorState::bind(&globals, &qh)?,
+        layer_shell: LayerShell::bind(&globals, &qh)?,
+        fractional_scale: globals
+            .bind(&qh, 1..=1, ())
+            .and_then(|manager| Ok((manager, globals.bind(&qh, 1..=1, ())?)))
+            .ok(),
+        loop_handle: event_loop.handle(),
+        display,
+
+        clock: Clock::scaled(animation.runtime.speed),
+        playback: animation.playback.clone(),
+        animation: Arc::new(animation),
         gpu,
         exit: false,
 
+        active: idle_notifier.is_none(),
+        dismissed: false,
+        lock,
+        keyboard: None,
+        pointer: None,
+        idle_notifier,
+        idle_notification: None,
         outputs: Vec::new(),
+        added_outputs: 0,
     };
 
     event_queue.roundtrip(&mut app)?;
 
-    let compositor_state = CompositorState::bind(&globals, &qh)?;
-    let layer_shell = LayerShell::bind(&globals, &qh)?;
+    WaylandSource::new(conn.clone(), event_queue)
+        .insert(event_loop.handle())
+        .map_err(|x| x.error)?;
 
-    let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
-        NonNull::new(conn.backend().display_ptr() as _).unwrap(),
-    ));
+    while !app.exit {
+        event_loop.dispatch(None, &mut app)?;
+    }
 
-    for output in app.output_state.outputs() {
-        let surface = compositor_state.create_surface(&qh);
-        let layer = layer_shell.create_layer_surface(
-            &qh,
-            surface,
-            Layer::Background,
-            Some(NAMESPACE),
-            Some(&output),
-        );
-        layer.commit();
+    // Make sure an unlock reaches the compositor before disconnecting
+    conn.roundtrip()?;
+    Ok(())
+}
+
+impl App {
+    /// Covers a newly announced output with a background layer surface, an
+    /// overlay when acting as a screensaver, or a lock surface when locking.
+    fn add_output(&mut self, qh: &QueueHandle<Self>, output: WlOutput) -> Result<()> {
+        let surface = self.compositor_state.create_surface(qh);
+        let shell = match &self.lock {
+            Some(lock) => Shell::Lock(lock.session.create_lock_surface(surface, &output, qh)),
+            None if self.dismiss_on_input() => {
+                let layer = self.layer_shell.create_layer_surface(
+                    qh,
+                    surface,
+                    Layer::Overlay,
+                    Some(NAMESPACE),
+                    Some(&output),
+                );
+                layer.set_anchor(Anchor::all());
+                layer.set_exclusive_zone(-1);
+                layer.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+                layer.commit();
+                Shell::Layer(layer)
+            }
+            None => {
+                let layer = self.layer_shell.create_layer_surface(
+                    qh,
+                    surface,
+                    Layer::Background,
+                    Some(NAMESPACE),
+                    Some(&output),
+                );
+                layer.commit();
+                Shell::Layer(layer)
+            }
+        };
 
+        let wl_surface = shell.wl_surface();
         let handle = SurfaceTargetUnsafe::RawHandle {
-            raw_display_handle,
+            raw_display_handle: self.display,
             raw_window_handle: RawWindowHandle::Wayland(WaylandWindowHandle::new(
-                NonNull::new(layer.wl_surface().id().as_ptr() as _).unwrap(),
+                NonNull::new(wl_surface.id().as_ptr() as _).unwrap(),
             )),
         };
-        let surface = unsafe { app.gpu.instance.create_surface_unsafe(handle)? };
+        let surface = unsafe { self.gpu.instance.create_surface_unsafe(handle)? };
+
+        let info = self.output_state.info(&output);
+        let scale = info.map_or(1, |x| x.scale_factor) as u32;
+        wl_surface.set_buffer_scale(scale as i32);
+
+        let fractional_scale = (self.fractional_scale.as_ref()).map(|(manager, viewporter)| {
+            (
+                manager.get_fractional_scale(wl_surface, qh, wl_surface.clone()),
+                viewporter.get_viewport(wl_surface, qh, ()),
+            )
+        });
+
+        let rt = &self.animation.runtime;
+        let pacer = Pacer::new(rt.max_fps, rt.adaptive);
+        let playback = self.playback();
+        let renderer = Renderer::with_playback(&self.gpu, self.animation.clone(), playback)
+            .with_clock(self.clock.clone());
+
+        let mut output = Output::new(output, shell, surface, scale, renderer, pacer);
+        output.fractional_scale = fractional_scale;
+        output.prompt = self.lock.is_some().then(|| Prompt::new(&self.gpu));
+        self.outputs.push(output);
+        self.layout();
+        Ok(())
+    }
 
-        let scale = app.output_state.info(&output).unwrap().scale_factor as u32;
-        layer.set_buffer_scale(scale).unwrap();
-        app.outputs.push(Output::new(layer, surface, scale));
+    /// Playback for a new output, the same as every other output unless
+    /// they are independent.
+    fn playback(&mut self) -> Playback {
+        let rt = &self.animation.runtime;
+        if !rt.independent_outputs {
+            return self.playback.clone();
+        }
+
+        let mut playback = self.animation.output_playback(self.added_outputs);
+        self.added_outputs += 1;
+        if rt.sync_colormap {
+            playback.colormap_offset = self.playback.colormap_offset;
+        }
+        playback
     }
 
-    while !app.exit {
-        event_queue.blocking_dispatch(&mut app)?;
+    fn dismiss_on_input(&self) -> bool {
+        let rt = &self.animation.runtime;
+        rt.exit_on_input || rt.idle.is_some()
     }
 
-    Ok(())
-}
+    /// Covers every output, when the seat goes idle.
+    fn show(&mut self, qh: &QueueHandle<Self>) {
+        self.active = true;
+        self.dismissed = false;
+        self.outputs.clear();
+
+        // Fades are timed from zero, so each activation starts a new clock
+        self.clock = Clock::scaled(self.animation.runtime.speed);
+
+        for output in self.output_state.outputs().collect::<Vec<_>>() {
+            if let Err(err) = self.add_output(qh, output) {
+                eprintln!("Failed to create surface for output: {err}");
+            }
+        }
+    }
+
+    /// Starts fading out every output on input, which are then removed as
+    /// they finish.
+    fn dismiss(&mut self) {
+        if !self.active || self.dismissed {
+            return;
+        }
+
+        self.dismissed = true;
+        for output in &mut self.outputs {
+            output.renderer.fade_out();
+        }
+    }
+
+    /// Removes an output once it has faded out, exiting after the last one
+    /// unless waiting for the next idle period.
+    fn hide(&mut self, output: usize) {
+        self.outputs.remove(output);
+        if self.outputs.is_empty() {
+            self.active = false;
+            self.exit |= self.idle_notifier.is_none();
+        }
+    }
+
+    fn remove_output(&mut self, output: &WlOutput) {
+        self.outputs.retain(|x| &x.output != output);
+        self.layout();
+    }
+
+    /// When spanning, gives each output its part of a canvas covering the
+    /// logical layout of every output.
+    fn layout(&mut self) {
+        if !self.animation.runtime.span_outputs {
+            return;
+        }
+
+        let rects = (self.outputs.iter())
+            .map(|x| {
+                let info = self.output_state.info(&x.output)?;
+                let (position, size) = (info.logical_position?, info.logical_size?);
+                Some((
+                    Vector2::new(position.0, position.1),
+                    Vector2::new(size.0, size.1),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let (mut min, mut max) = (Vector2::repeat(i32::MAX), Vector2::repeat(i32::MIN));
+        for (position, size) in rects.iter().flatten() {
+            min = min.inf(position);
+            max = max.sup(&(position + size));
+        }
+
+        for (output, rect) in self.outputs.iter_mut().zip(rects) {
+            let viewport = rect.map(|(position, size)| Viewport {
+                canvas: (max - min).cast(),
+                offset: (position - min).cast(),
+                size: size.cast(),
+            });
+            output.renderer.set_viewport(viewport);
+        }
+    }
 
-impl App {
     fn layer_for_surface(&self, surface: &WlSurface) -> Option<usize> {
         self.outputs
             .iter()
-            .position(|x| x.layer.wl_surface().id() == surface.id())
+            .position(|x| x.shell.wl_surface().id() == surface.id())
+    }
+
+    /// Applies a size sent by the compositor to the output with `surface`.
+    fn resize(&mut self, qh: &QueueHandle<Self>, surface: &WlSurface, size: (u32, u32)) {
+        let Some(output) = self.layer_for_surface(surface) else {
+            return;
+        };
+
+        self.outputs[output].logical_size = Vector2::new(size.0, size.1);
+        self.configure(qh, output);
+        self.render(output);
     }
 
-    fn configure(&mut self, qh: &QueueHandle<Self>, layer: usize, size: Vector2<u32>) {
+    fn authenticated(&mut self, success: bool) {
+        if let Some(lock) = &mut self.lock {
+            self.exit |= lock.authenticated(success);
+        }
+    }
+
+    /// Resizes the buffer of an output to match its logical size and scale.
+    fn configure(&mut self, qh: &QueueHandle<Self>, layer: usize) {
         let output = &mut self.outputs[layer];
+        let logical = output.logical_size;
+        if logical == Vector2::zeros() {
+            return;
+        }
+
+        // Buffers are always drawn upright, leaving rotated outputs to the
+        // compositor, so the logical size is already in the right orientation.
+        let size = match (&output.fractional_scale, output.preferred_scale) {
+            (Some((_, viewport)), Some(scale)) => {
+                output.shell.wl_surface().set_buffer_scale(1);
+                viewport.set_destination(logical.x as i32, logical.y as i32);
+                logical.map(|x| (x as f64 * scale).round() as u32)
+            }
+            _ => {
+                (output.shell.wl_surface()).set_buffer_scale(output.scale_factor as i32);
+                logical * output.scale_factor
+            }
+        };
 
         let surface_config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
@@ -158,11 +469,54 @@ impl App {
         output.size = size;
 
         if mem::take(&mut output.needs_config) {
-            let wl_surface = output.layer.wl_surface();
+            let wl_surface = output.shell.wl_surface();
             wl_surface.frame(qh, wl_surface.clone());
         }
     }
 
+    /// Redraws an output at its new scale or orientation.
+    fn rescale(&mut self, qh: &QueueHandle<Self>, output: usize) {
+        if self.outputs[output].logical_size != Vector2::zeros() {
+            self.configure(qh, output);
+            self.render(output);
+        }
+    }
+
+    /// Renders a frame and schedules the next one, either on the next frame
+    /// callback or once the output's pacer says it's due.
+    fn draw(&mut self, qh: &QueueHandle<Self>, index: usize) {
+        self.render(index);
+
+        let output = &mut self.outputs[index];
+        if self.dismissed && output.renderer.faded_out() {
+            self.hide(index);
+            return;
+        }
+
+        let wl_surface = output.shell.wl_surface();
+        wl_surface.damage_buffer(0, 0, output.size.x as i32, output.size.y as i32);
+
+        match output.pacer.next_frame() {
+            Some(next) => {
+                let (qh, surface) = (qh.clone(), wl_surface.clone());
+                let timer = Timer::from_deadline(next);
+                self.loop_handle
+                    .insert_source(timer, move |_, _, app| {
+                        if let Some(output) = app.layer_for_surface(&surface) {
+                            app.draw(&qh, output);
+                        }
+                        TimeoutAction::Drop
+                    })
+                    .unwrap();
+            }
+            None => {
+                wl_surface.frame(qh, wl_surface.clone());
+            }
+        }
+
+        wl_surface.commit();
+    }
+
     fn render(&mut self, output: usize) {
         let output = &mut self.outputs[output];
 
@@ -188,8 +542,15 @@ impl App {
                 occlusion_query_set: None,
             });
 
-            self.renderer
-                .render(&self.gpu, output.size, &mut render_pass);
+            output
+                .renderer
+                .render(&self.gpu, output.size, &mut output.pacer, &mut render_pass);
+
+            if let (Some(prompt), Some(lock)) = (&mut output.prompt, &self.lock) {
+                let scale = output.size.x as f32 / output.logical_size.x as f32;
+                let color = output.renderer.foreground();
+                prompt.paint(&self.gpu, &mut render_pass, output.size, scale, color, lock);
+            }
         }
 
         self.gpu.queue.submit(Some(encoder.finish()));
@@ -198,13 +559,46 @@ impl App {
 }
 
 impl Output {
-    pub fn new(layer: LayerSurface, surface: Surface<'static>, scale_factor: u32) -> Self {
+    pub fn new(
+        output: WlOutput,
+        shell: Shell,
+        surface: Surface<'static>,
+        scale_factor: u32,
+        renderer: Renderer,
+        pacer: Pacer,
+    ) -> Self {
         Self {
             surface,
-            layer,
+            shell,
+            output,
+            fractional_scale: None,
+
             scale_factor,
+            preferred_scale: None,
             needs_config: true,
+            logical_size: Vector2::zeros(),
             size: Vector2::zeros(),
+            renderer,
+            pacer,
+            prompt: None,
+        }
+    }
+}
+
+impl Shell {
+    fn wl_surface(&self) -> &WlSurface {
+        match self {
+            Self::Layer(layer) => layer.wl_surface(),
+            Self::Lock(lock) => lock.wl_surface(),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some((fractional_scale, viewport)) = &self.fractional_scale {
+            fractional_scale.destroy();
+            viewport.destroy();
         }
     }
 }
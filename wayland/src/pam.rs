@@ -0,0 +1,169 @@
+//! Just enough of libpam to check a password.
+
+use std::{
+    ffi::{CStr, CString, c_char, c_int, c_void},
+    mem,
+    path::Path,
+    ptr,
+};
+
+use anyhow::{Result, bail, ensure};
+
+use crate::lock;
+
+/// Service file shipped in `wayland/pam`, which has to be installed into
+/// `/etc/pam.d` for passwords to be accepted.
+pub const SERVICE: &str = "macintosh-screensaver";
+const SERVICE_DIRS: [&str; 3] = ["/etc/pam.d", "/usr/lib/pam.d", "/usr/etc/pam.d"];
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: unsafe extern "C" fn(
+        c_int,
+        *mut *const PamMessage,
+        *mut *mut PamResponse,
+        *mut c_void,
+    ) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[link(name = "pam")]
+unsafe extern "C" {
+    fn pam_start(
+        service: *const c_char,
+        user: *const c_char,
+        conv: *const PamConv,
+        handle: *mut *mut c_void,
+    ) -> c_int;
+    fn pam_authenticate(handle: *mut c_void, flags: c_int) -> c_int;
+    fn pam_acct_mgmt(handle: *mut c_void, flags: c_int) -> c_int;
+    fn pam_end(handle: *mut c_void, status: c_int) -> c_int;
+}
+
+/// Makes sure the service file is installed before locking, as PAM falls
+/// back to the `other` service otherwise, which usually rejects everything
+/// and would leave the session locked for good.
+pub fn check_service() -> Result<()> {
+    let installed = (SERVICE_DIRS.iter()).any(|dir| Path::new(dir).join(SERVICE).exists());
+    ensure!(
+        installed,
+        "The PAM service file isn't installed, copy wayland/pam/{SERVICE} to /etc/pam.d"
+    );
+    Ok(())
+}
+
+/// Checks `password` for the user running the process. Blocks for a while on
+/// failure, so should be called off the main thread.
+pub fn authenticate(password: &str) -> Result<()> {
+    ensure!(
+        !password.contains('\0'),
+        "Passwords can't contain null bytes"
+    );
+    let mut password = [password.as_bytes(), &[0]].concat();
+    let result = check(&password);
+    lock::zero(&mut password);
+    result
+}
+
+/// Name of the user running the process, looked up by uid since `$USER` can
+/// be set to anything.
+fn user() -> Result<CString> {
+    let mut buf = vec![0 as c_char; 1024];
+    loop {
+        let mut passwd = unsafe { mem::zeroed::<libc::passwd>() };
+        let mut result = ptr::null_mut();
+        let status = unsafe {
+            libc::getpwuid_r(
+                libc::getuid(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        match status {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if !result.is_null() => {
+                return Ok(unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned());
+            }
+            0 => bail!("No user has the current uid"),
+            _ => bail!("Failed to look up the current user ({status})"),
+        }
+    }
+}
+
+/// Runs the PAM conversation with a null-terminated `password`.
+fn check(password: &[u8]) -> Result<()> {
+    let user = user()?;
+    let service = CString::new(SERVICE)?;
+
+    let conv = PamConv {
+        conv: converse,
+        appdata_ptr: password.as_ptr() as *mut c_void,
+    };
+
+    unsafe {
+        let mut handle = ptr::null_mut();
+        let status = pam_start(service.as_ptr(), user.as_ptr(), &conv, &mut handle);
+        if status != PAM_SUCCESS {
+            bail!("Failed to start PAM ({status})");
+        }
+
+        let mut status = pam_authenticate(handle, 0);
+        if status == PAM_SUCCESS {
+            status = pam_acct_mgmt(handle, 0);
+        }
+
+        pam_end(handle, status);
+        if status != PAM_SUCCESS {
+            bail!("Authentication failed ({status})");
+        }
+    }
+
+    Ok(())
+}
+
+/// Answers every prompt with the password. PAM frees the responses, so they
+/// have to come from the C allocator.
+unsafe extern "C" fn converse(
+    count: c_int,
+    messages: *mut *const PamMessage,
+    responses: *mut *mut PamResponse,
+    password: *mut c_void,
+) -> c_int {
+    unsafe {
+        let count = count.max(0) as usize;
+        let out = libc::calloc(count, size_of::<PamResponse>()) as *mut PamResponse;
+        if out.is_null() {
+            return PAM_BUF_ERR;
+        }
+
+        for i in 0..count {
+            let message = &**messages.add(i);
+            if matches!(message.msg_style, PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON) {
+                (*out.add(i)).resp = libc::strdup(password as *const c_char);
+            }
+        }
+
+        *responses = out;
+        PAM_SUCCESS
+    }
+}
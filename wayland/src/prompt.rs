@@ -0,0 +1,119 @@
+use nalgebra::{Matrix4, Vector2, Vector3};
+use wgpu::{
+    Buffer, BufferUsages, IndexFormat, RenderPass,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+
+use crate::lock::Lock;
+use macintosh_wallpaper::{
+    animation::Image,
+    pipelines::{
+        Gpu,
+        pixel::{PixelsPipeline, PixelsUniform},
+    },
+};
+
+/// Most characters shown as dots, longer passwords still work.
+const MAX_DOTS: usize = 16;
+/// Size of a prompt pixel in logical pixels.
+const PIXEL_SIZE: f32 = 4.0;
+
+/// Password field drawn over the animation in the same style as the scenes,
+/// with a dot for each typed character.
+pub struct Prompt {
+    pixels: PixelsPipeline,
+    index: Buffer,
+    size: Vector2<u32>,
+}
+
+impl Prompt {
+    pub fn new(gpu: &Gpu) -> Self {
+        let index: [u16; 6] = [0, 1, 2, 2, 3, 0];
+        let index = gpu.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&index),
+            usage: BufferUsages::INDEX,
+        });
+
+        let image = image(0);
+        Self {
+            pixels: PixelsPipeline::new(gpu, image.byte_size()),
+            index,
+            size: image.size,
+        }
+    }
+
+    /// Draws the field centered on a target of `size` physical pixels, where
+    /// `scale` is the number of physical pixels in a logical one.
+    pub fn paint(
+        &mut self,
+        gpu: &Gpu,
+        render_pass: &mut RenderPass,
+        size: Vector2<u32>,
+        scale: f32,
+        color: Vector3<f32>,
+        lock: &Lock,
+    ) {
+        // The dot count stands in for the scene index, so the image is only
+        // rebuilt when it changes.
+        let dots = lock.password.chars().count().min(MAX_DOTS);
+        self.pixels.upload(gpu, dots, &[image(dots)]);
+
+        let extent = self.size.cast::<f32>() * PIXEL_SIZE * scale;
+        let view = Matrix4::new_nonuniform_scaling(&Vector3::new(
+            extent.x / size.x as f32,
+            extent.y / size.y as f32,
+            1.0,
+        ));
+
+        self.pixels.prepare(
+            gpu,
+            &PixelsUniform {
+                view,
+                image_size: self.size,
+                window_size: size,
+                image_offset: 0,
+                color,
+                scale: 1.0,
+                progress: 1.0,
+                progress_angle: 0.0,
+
+                opacity: if lock.checking { 0.5 } else { 1.0 },
+                dissolve: 1.0,
+                wipe: Vector3::z(),
+            },
+        );
+
+        render_pass.set_index_buffer(self.index.slice(..), IndexFormat::Uint16);
+        self.pixels.paint(render_pass);
+    }
+}
+
+/// A one pixel border around a row of 2x2 dots, one for each of the first
+/// `dots` slots.
+fn image(dots: usize) -> Image {
+    let size = Vector2::new(MAX_DOTS as u32 * 4 + 4, 8);
+    let mut data = vec![u32::MAX; (size.x * size.y).div_ceil(32) as usize];
+    let mut set = |x: u32, y: u32| {
+        let idx = y * size.x + x;
+        data[idx as usize / 32] &= !(1 << (idx % 32));
+    };
+
+    for x in 0..size.x {
+        set(x, 0);
+        set(x, size.y - 1);
+    }
+
+    for y in 0..size.y {
+        set(0, y);
+        set(size.x - 1, y);
+    }
+
+    for dot in 0..dots as u32 {
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            set(3 + dot * 4 + x, 3 + y);
+        }
+    }
+
+    Image { data, size }
+}